@@ -23,7 +23,7 @@ pub struct Item {
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let mut analyzer = Analyzer::default();
 ///
 /// // Call push_state at each tick.
@@ -3,6 +3,7 @@ use crate::{
     geom::*,
     models::{GameCommand, PLAYER_MAX_THROTTLE, PLAYER_MIN_THROTTLE},
 };
+use log::debug;
 use rand::{thread_rng, Rng};
 use std::{collections::VecDeque, fmt::Debug, time::Duration};
 
@@ -19,7 +20,7 @@ use std::{collections::VecDeque, fmt::Debug, time::Duration};
 ///
 /// A stateful usage of `Behavior`.
 ///
-/// ```
+/// ```ignore
 /// impl Handlar for Player {
 ///     fn tick(...) {
 ///         self.analyzer.push_state(state, Instant::now());
@@ -53,7 +54,7 @@ use std::{collections::VecDeque, fmt::Debug, time::Duration};
 ///
 /// A stateless usage of `Behavior`.
 ///
-/// ```
+/// ```ignore
 /// impl Handlar for Player {
 ///     fn tick(...) {
 ///         self.analyzer.push_state(state, Instant::now());
@@ -370,7 +371,7 @@ impl FireAt {
 
 /// A `Behavior` to send a random command.
 #[derive(Clone, Debug)]
-struct Random;
+pub struct Random;
 
 impl Behavior for Random {
     fn next_command(&mut self, _: &Analyzer) -> Option<GameCommand> {
@@ -532,7 +533,7 @@ impl Behavior for DodgePlayer {
         }
 
         if let Some(player) = analyzer.players_within_colliding(400.0, Duration::from_secs(2), false).next() {
-            println!("Player will collide: {}, velocity: {}", player.id, player.velocity);
+            debug!("Player will collide: {}, velocity: {}", player.id, player.velocity);
             let angle = player.velocity.tangent();
             self.next = Sequence::with_slice(&[
                 &Throttle::max(),
@@ -541,7 +542,7 @@ impl Behavior for DodgePlayer {
             return self.next.next_command(analyzer);
         }
         if let Some(player) = analyzer.players_within_colliding(400.0, Duration::from_secs(2), true).next() {
-            println!("chased by: {}, counter attack", player.id);
+            debug!("chased by: {}, counter attack", player.id);
             let angle = player.velocity.tangent();
             self.next = Sequence::with_slice(&[
                 &FireAt::with_times(Target::Id(player.id), 2),
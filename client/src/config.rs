@@ -0,0 +1,346 @@
+use failure::{format_err, Error};
+use std::{env, fmt, path::PathBuf, str::FromStr, time::Duration};
+use url::Url;
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_PATH: &str = "/socket";
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Ws,
+    Wss,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scheme::Ws => write!(f, "ws"),
+            Scheme::Wss => write!(f, "wss"),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = Error;
+
+    fn from_str(scheme: &str) -> Result<Scheme, Error> {
+        match scheme {
+            "ws" => Ok(Scheme::Ws),
+            "wss" => Ok(Scheme::Wss),
+            _ => Err(format_err!("Unknown scheme {}, expected ws or wss", scheme)),
+        }
+    }
+}
+
+/// Certificates to trust for `wss://`, on top of the system ones.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// PEM file with a self-signed or private CA certificate.
+    pub ca_cert: Option<PathBuf>,
+    /// Trusts any certificate at all. Only for testing.
+    pub accept_invalid_certs: bool,
+}
+
+/// How to reconnect after losing the connection. The delay doubles with
+/// every failed attempt, up to `max_delay`, and a random part of it is
+/// shaved off so a crowd of bots doesn't come back all at once.
+#[derive(Debug, Clone)]
+pub struct ReconnectOptions {
+    pub enabled: bool,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Attempts in a row before giving up, or `None` to keep trying.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> ReconnectOptions {
+        ReconnectOptions {
+            enabled: true,
+            min_delay: DEFAULT_MIN_RECONNECT_DELAY,
+            max_delay: DEFAULT_MAX_RECONNECT_DELAY,
+            max_attempts: None,
+        }
+    }
+}
+
+/// Where and how `run_with_config` connects, `ws://localhost:8080/socket` by
+/// default. Build one with `ClientConfig::builder()`, or take it from the
+/// environment and command line with `ClientConfig::from_env_and_args()`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub scheme: Scheme,
+    pub host: String,
+    /// The default port of the scheme if `None`.
+    pub port: Option<u16>,
+    pub path: String,
+    /// The room to play in, the main game if `None`.
+    pub room: Option<String>,
+    /// Extra query parameters for the socket URL, e.g. `("queue", "ranked")`.
+    /// Setting one again through the builder replaces it.
+    pub query: Vec<(String, String)>,
+    /// How long connecting and the websocket handshake may take.
+    pub connect_timeout: Duration,
+    pub tls: TlsOptions,
+    pub reconnect: ReconnectOptions,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            scheme: Scheme::Ws,
+            host: DEFAULT_HOST.to_string(),
+            port: Some(DEFAULT_PORT),
+            path: DEFAULT_PATH.to_string(),
+            room: None,
+            query: vec![],
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            tls: TlsOptions::default(),
+            reconnect: ReconnectOptions::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn builder() -> ClientConfigBuilder {
+        ClientConfigBuilder { config: ClientConfig::default() }
+    }
+
+    /// The defaults, overridden by the environment variables and then by
+    /// the command line flags described at `ClientConfigBuilder`.
+    pub fn from_env_and_args() -> Result<ClientConfig, Error> {
+        Ok(ClientConfig::builder().env()?.args(env::args().skip(1))?.build())
+    }
+
+    /// The socket URL for a bot with the API key `key` and team name `name`.
+    pub fn url(&self, key: &str, name: &str) -> Result<Url, Error> {
+        let mut url = Url::parse(&format!("{}://{}", self.scheme, self.host))
+            .map_err(|e| format_err!("Invalid host {}: {}", self.host, e))?;
+        url.set_port(self.port).map_err(|_| format_err!("Can't set a port on {}", self.host))?;
+        url.set_path(&self.path);
+
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("key", key).append_pair("name", name);
+            if let Some(ref room) = self.room {
+                query.append_pair("room", room);
+            }
+            for (name, value) in &self.query {
+                query.append_pair(name, value);
+            }
+        }
+
+        Ok(url)
+    }
+}
+
+/// Builds a `ClientConfig` out of setters, environment variables and command
+/// line flags, each overriding what came before.
+///
+/// | Variable | Flag | |
+/// |--|--|--|
+/// | `SERVER_HOST` | `--server <url>` | Scheme, host, port and path in one, `wss://` if no scheme is given |
+/// | | `--scheme <ws\|wss>`, `--host <host>`, `--port <port>`, `--path <path>` | Parts of the URL |
+/// | `ROOM` | `--room <room>` | |
+/// | `QUEUE` | `--queue <queue>` | `ranked` for matchmade matches |
+/// | | `--query <name>=<value>` | Extra query parameters, can be repeated |
+/// | `CONNECT_TIMEOUT_SECS` | `--connect-timeout <secs>` | |
+/// | `CA_CERT` | `--ca-cert <path>` | |
+/// | `ACCEPT_INVALID_CERTS=1` | `--accept-invalid-certs` | |
+/// | `RECONNECT=0` | `--no-reconnect` | Exit when the connection drops |
+/// | `MAX_RECONNECT_ATTEMPTS` | `--max-reconnect-attempts <n>` | Attempts in a row before giving up |
+#[derive(Debug, Clone)]
+pub struct ClientConfigBuilder {
+    config: ClientConfig,
+}
+
+impl ClientConfigBuilder {
+    pub fn scheme(mut self, scheme: Scheme) -> ClientConfigBuilder {
+        self.config.scheme = scheme;
+        self
+    }
+
+    pub fn host<S: Into<String>>(mut self, host: S) -> ClientConfigBuilder {
+        self.config.host = host.into();
+        self
+    }
+
+    pub fn port(mut self, port: Option<u16>) -> ClientConfigBuilder {
+        self.config.port = port;
+        self
+    }
+
+    pub fn path<S: Into<String>>(mut self, path: S) -> ClientConfigBuilder {
+        self.config.path = path.into();
+        self
+    }
+
+    /// Sets the scheme, host, port and path from a URL like
+    /// `ws://localhost:8080` or `tokyo.example.com/socket`. Without a scheme
+    /// `wss://` is assumed, without a path the current one is kept.
+    pub fn server(mut self, server: &str) -> Result<ClientConfigBuilder, Error> {
+        let server =
+            if server.contains("://") { server.to_string() } else { format!("wss://{}", server) };
+        let url =
+            Url::parse(&server).map_err(|e| format_err!("Invalid server {}: {}", server, e))?;
+
+        self.config.scheme = url.scheme().parse()?;
+        self.config.host = url.host_str().ok_or_else(|| format_err!("No host in {}", url))?.into();
+        self.config.port = url.port();
+        if url.path() != "/" {
+            self.config.path = url.path().to_string();
+        }
+
+        Ok(self)
+    }
+
+    pub fn room<S: Into<String>>(mut self, room: S) -> ClientConfigBuilder {
+        self.config.room = Some(room.into());
+        self
+    }
+
+    pub fn query<N: Into<String>, V: Into<String>>(
+        mut self,
+        name: N,
+        value: V,
+    ) -> ClientConfigBuilder {
+        let name = name.into();
+        self.config.query.retain(|(existing, _)| *existing != name);
+        self.config.query.push((name, value.into()));
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> ClientConfigBuilder {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn ca_cert<P: Into<PathBuf>>(mut self, path: P) -> ClientConfigBuilder {
+        self.config.tls.ca_cert = Some(path.into());
+        self
+    }
+
+    pub fn accept_invalid_certs(mut self, accept: bool) -> ClientConfigBuilder {
+        self.config.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    pub fn reconnect(mut self, enabled: bool) -> ClientConfigBuilder {
+        self.config.reconnect.enabled = enabled;
+        self
+    }
+
+    pub fn reconnect_delay(mut self, min: Duration, max: Duration) -> ClientConfigBuilder {
+        self.config.reconnect.min_delay = min;
+        self.config.reconnect.max_delay = max.max(min);
+        self
+    }
+
+    pub fn max_reconnect_attempts(mut self, attempts: Option<u32>) -> ClientConfigBuilder {
+        self.config.reconnect.max_attempts = attempts;
+        self
+    }
+
+    /// Applies the environment variables which are set.
+    pub fn env(mut self) -> Result<ClientConfigBuilder, Error> {
+        if let Ok(server) = env::var("SERVER_HOST") {
+            self = self.server(&server)?;
+        }
+        if let Ok(room) = env::var("ROOM") {
+            self = self.room(room);
+        }
+        if let Ok(queue) = env::var("QUEUE") {
+            self = self.query("queue", queue);
+        }
+        if let Ok(secs) = env::var("CONNECT_TIMEOUT_SECS") {
+            let secs = secs.parse().map_err(|_| format_err!("Invalid CONNECT_TIMEOUT_SECS"))?;
+            self = self.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Ok(path) = env::var("CA_CERT") {
+            self = self.ca_cert(path);
+        }
+        if let Ok(accept) = env::var("ACCEPT_INVALID_CERTS") {
+            self = self.accept_invalid_certs(accept == "1" || accept == "true");
+        }
+        if let Ok(reconnect) = env::var("RECONNECT") {
+            self = self.reconnect(reconnect != "0" && reconnect != "false");
+        }
+        if let Ok(attempts) = env::var("MAX_RECONNECT_ATTEMPTS") {
+            let attempts =
+                attempts.parse().map_err(|_| format_err!("Invalid MAX_RECONNECT_ATTEMPTS"))?;
+            self = self.max_reconnect_attempts(Some(attempts));
+        }
+
+        Ok(self)
+    }
+
+    /// Applies the flags in `args`, taking `--flag value` and `--flag=value`.
+    /// Anything else is left for the bot to parse.
+    pub fn args<I: IntoIterator<Item = String>>(
+        mut self,
+        args: I,
+    ) -> Result<ClientConfigBuilder, Error> {
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.find('=') {
+                Some(index) if arg.starts_with("--") => {
+                    (arg[..index].to_string(), Some(arg[index + 1..].to_string()))
+                },
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format_err!("{} needs a value", flag))
+            };
+
+            self = match flag.as_str() {
+                "--server" => self.server(&value()?)?,
+                "--scheme" => self.scheme(value()?.parse()?),
+                "--host" => self.host(value()?),
+                "--port" => {
+                    let port = value()?;
+                    self.port(Some(port.parse().map_err(|_| format_err!("Invalid port {}", port))?))
+                },
+                "--path" => self.path(value()?),
+                "--room" => self.room(value()?),
+                "--queue" => self.query("queue", value()?),
+                "--query" => {
+                    let pair = value()?;
+                    let index = pair
+                        .find('=')
+                        .ok_or_else(|| format_err!("--query takes <name>=<value>, got {}", pair))?;
+                    self.query(&pair[..index], &pair[index + 1..])
+                },
+                "--connect-timeout" => {
+                    let secs = value()?;
+                    let secs = secs.parse().map_err(|_| format_err!("Invalid timeout {}", secs))?;
+                    self.connect_timeout(Duration::from_secs(secs))
+                },
+                "--ca-cert" => self.ca_cert(value()?),
+                "--accept-invalid-certs" => self.accept_invalid_certs(true),
+                "--no-reconnect" => self.reconnect(false),
+                "--max-reconnect-attempts" => {
+                    let attempts = value()?;
+                    let attempts = attempts
+                        .parse()
+                        .map_err(|_| format_err!("Invalid attempts {}", attempts))?;
+                    self.max_reconnect_attempts(Some(attempts))
+                },
+                _ => self,
+            };
+        }
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> ClientConfig {
+        self.config
+    }
+}
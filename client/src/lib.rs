@@ -8,31 +8,154 @@ extern crate serde_derive;
 
 pub mod analyzer;
 pub mod behavior;
+pub mod config;
 pub mod geom;
 pub mod models;
 
-use crate::models::{ClientState, GameCommand, GameState, ServerToClient, MIN_COMMAND_INTERVAL};
-use failure::Error;
-use futures::{Future, Sink, Stream};
+pub use crate::config::ClientConfig;
+
+use crate::{
+    config::{ReconnectOptions, TlsOptions},
+    models::{ClientState, GameCommand, GameState, ServerToClient, MIN_COMMAND_INTERVAL},
+};
+use failure::{format_err, Error};
+use futures::{
+    future::{self, Either, Loop},
+    Future, Sink, Stream,
+};
+use rand::Rng;
 use std::{
-    env,
-    fmt::Debug,
+    collections::HashMap,
+    fmt::{self, Debug, Display},
+    net::ToSocketAddrs,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    timer::{timeout, Delay, Timeout},
 };
 use tokio_tungstenite as tokio_ws;
 use tokio_ws::tungstenite as ws;
-use url::{
-    percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET},
-    Url,
-};
+use url::Url;
+
+// A connection which lasted this long starts the reconnect delays over
+const STABLE_CONNECTION: Duration = Duration::from_secs(10);
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_POLICY: u16 = 1008;
+const CLOSE_NO_STATUS: u16 = 1005;
 
 /// `Handler` is provided as the trait that players can implement to interact
-/// with the game server.
+/// with the game server. Only `tick` is required, the other methods are hooks
+/// into the life of the connection and the player which do nothing unless
+/// implemented.
 pub trait Handler {
     /// An opportunity, provided multiple times a second, to analyze the current
     /// state of the world and do a single action based on its state. It's not
-    /// called when the player is dead and waiting to be respawn.
+    /// called when the player is dead and waiting to be respawn, `on_state`
+    /// is.
     fn tick(&mut self, state: &ClientState) -> Option<GameCommand>;
+
+    /// The server assigned the player ID `id`. This happens on every
+    /// connection, and again at the start of every matchmade match.
+    fn on_connect(&mut self, _id: u32) {}
+
+    /// Every state the server sends, including while the player is dead.
+    fn on_state(&mut self, _state: &ClientState) {}
+
+    /// The player was shot down by the player with the ID `killer`.
+    fn on_death(&mut self, _killer: u32) {}
+
+    /// The player is back in the game after dying.
+    fn on_respawn(&mut self) {}
+
+    /// The team names by player ID, sent whenever someone joins or leaves.
+    fn on_team_names(&mut self, _team_names: &HashMap<u32, String>) {}
+
+    /// The connection ended. Unless the reason is fatal, a reconnect follows
+    /// and `on_connect` is called again.
+    fn on_disconnect(&mut self, _reason: &Disconnect) {}
+}
+
+/// Why a connection to the server ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disconnect {
+    /// The server couldn't be reached, or the handshake took too long.
+    ConnectFailed(String),
+    /// The server turned the connection down with this HTTP status, e.g. for
+    /// an invalid or banned API key.
+    Rejected(u16),
+    /// The server closed the connection with this websocket close code.
+    Closed { code: u16, reason: String },
+    /// The connection dropped without the server closing it.
+    Lost(String),
+}
+
+impl Disconnect {
+    /// Whether reconnecting is pointless: the API key was refused, the bot
+    /// was kicked or banned, or another client took over the API key.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Disconnect::Rejected(status) => *status >= 400 && *status < 500 && *status != 429,
+            Disconnect::Closed { code, .. } => *code == CLOSE_POLICY || *code == CLOSE_NORMAL,
+            Disconnect::ConnectFailed(_) | Disconnect::Lost(_) => false,
+        }
+    }
+}
+
+impl Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Disconnect::ConnectFailed(e) => write!(f, "Failed to connect: {}", e),
+            Disconnect::Rejected(status) => write!(f, "The server refused with HTTP {}", status),
+            Disconnect::Closed { code, reason } if reason.is_empty() => {
+                write!(f, "The server closed the connection ({})", code)
+            },
+            Disconnect::Closed { code, reason } => {
+                write!(f, "The server closed the connection ({}): {}", code, reason)
+            },
+            Disconnect::Lost(e) => write!(f, "Lost the connection: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Disconnect {}
+
+/// Exponential backoff with jitter between reconnects.
+struct Backoff {
+    options: ReconnectOptions,
+    attempts: u32,
+}
+
+impl Backoff {
+    fn new(options: ReconnectOptions) -> Backoff {
+        Backoff { options, attempts: 0 }
+    }
+
+    /// The delay before the next attempt, or `None` to give up.
+    fn next_delay(&mut self) -> Option<Duration> {
+        if !self.options.enabled
+            || self.options.max_attempts.is_some_and(|max| self.attempts >= max)
+        {
+            return None;
+        }
+
+        let factor = 1u32 << self.attempts.min(16);
+        let delay = self
+            .options
+            .min_delay
+            .checked_mul(factor)
+            .map_or(self.options.max_delay, |delay| delay.min(self.options.max_delay));
+        self.attempts += 1;
+
+        // Half the delay is fixed, the other half random
+        Some(delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0, 0.5)))
+    }
+
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
 }
 
 fn log_err<E: Debug>(e: E) {
@@ -46,7 +169,7 @@ fn is_player_alive(state: &ClientState) -> bool {
 fn build_game_loop<H, S, D>(
     sink: S,
     client_state: Arc<Mutex<ClientState>>,
-    mut handler: H,
+    handler: Arc<Mutex<H>>,
 ) -> impl Future<Item = (), Error = ()>
 where
     H: Handler + Send + 'static,
@@ -59,7 +182,7 @@ where
         .filter_map(move |_| {
             let client_state = &*client_state.lock().unwrap();
             if is_player_alive(client_state) {
-                handler.tick(client_state)
+                handler.lock().unwrap().tick(client_state)
             } else {
                 None
             }
@@ -75,69 +198,458 @@ where
         .map(|_| ()) // throw away leftovers from forward
 }
 
-fn build_state_updater<S, D>(
+fn build_state_updater<H, S>(
     stream: S,
     client_state: Arc<Mutex<ClientState>>,
-) -> impl Future<Item = (), Error = ()>
+    handler: Arc<Mutex<H>>,
+) -> impl Future<Item = Disconnect, Error = ()>
 where
-    S: Stream<Item = ws::Message, Error = D>,
-    D: Debug,
+    H: Handler + Send + 'static,
+    S: Stream<Item = ws::Message, Error = ws::Error>,
 {
+    // Whether the player was alive in the last state, to tell deaths and
+    // respawns apart. Unknown until the first state of a game.
+    let mut was_alive: Option<bool> = None;
+
     stream
-        // We only care about text websocket messages.
-        .filter_map(|message| message.into_text().ok())
-        // We especially only care about proper JSON messages.
-        .filter_map(|message| serde_json::from_str(&message).ok())
-        // Update the our game state to the most recent reported by the server.
-        .for_each(move |server_to_client_msg| {
+        // A close frame from the server ends the stream with an error
+        .map_err(|e| match e {
+            ws::Error::ConnectionClosed(frame) => closed(frame),
+            e => Disconnect::Lost(e.to_string()),
+        })
+        .for_each(move |message| {
+            // We only care about text websocket messages.
+            let text = match message {
+                ws::Message::Text(text) => text,
+                _ => return Ok(()),
+            };
+
+            // We especially only care about proper JSON messages.
+            let server_to_client_msg = match serde_json::from_str(&text) {
+                Ok(server_to_client_msg) => server_to_client_msg,
+                Err(_) => return Ok(()),
+            };
+
+            // Update the our game state to the most recent reported by the server.
             match server_to_client_msg {
                 ServerToClient::Id(player_id) => {
                     (*client_state).lock().unwrap().id = player_id;
+                    was_alive = None;
+                    handler.lock().unwrap().on_connect(player_id);
                 },
                 ServerToClient::GameState(state) => {
-                    (*client_state).lock().unwrap().game_state = state;
+                    let mut client_state = client_state.lock().unwrap();
+                    client_state.game_state = state;
+
+                    let mut handler = handler.lock().unwrap();
+                    let alive = is_player_alive(&client_state);
+                    match (was_alive, alive) {
+                        (Some(true), false) => {
+                            let id = client_state.id;
+                            let dead = &client_state.game_state.dead;
+                            if let Some(dead) = dead.iter().find(|dead| dead.player.id == id) {
+                                handler.on_death(dead.killer);
+                            }
+                        },
+                        (Some(false), true) => handler.on_respawn(),
+                        _ => {},
+                    }
+                    was_alive = Some(alive);
+
+                    handler.on_state(&client_state);
+                },
+                ServerToClient::TeamNames(team_names) => {
+                    handler.lock().unwrap().on_team_names(&team_names);
+                },
+                // Forget the last match so we don't act on it while waiting
+                ServerToClient::Queue(_) | ServerToClient::Match(_) => {
+                    (*client_state).lock().unwrap().game_state = GameState::default();
+                    was_alive = None;
+                },
+                ServerToClient::Warning(warning) => {
+                    eprintln!("Warning from the server: {}", warning);
                 },
                 _ => {},
             }
 
             Ok(())
         })
-        .map_err(log_err)
+        .then(|result| match result {
+            Ok(()) => Ok(Disconnect::Lost("The connection was closed".to_string())),
+            Err(disconnect) => Ok(disconnect),
+        })
+}
+
+fn closed(frame: Option<ws::protocol::CloseFrame>) -> Disconnect {
+    match frame {
+        Some(frame) => {
+            Disconnect::Closed { code: frame.code.into(), reason: frame.reason.into_owned() }
+        },
+        None => Disconnect::Closed { code: CLOSE_NO_STATUS, reason: String::new() },
+    }
+}
+
+fn handshake_error(e: ws::Error) -> Disconnect {
+    match e {
+        ws::Error::Http(status) => Disconnect::Rejected(status),
+        e => Disconnect::ConnectFailed(e.to_string()),
+    }
+}
+
+fn timeout_error(e: timeout::Error<Disconnect>) -> Disconnect {
+    if e.is_elapsed() {
+        return Disconnect::ConnectFailed("Timed out".to_string());
+    }
+
+    e.into_inner().unwrap_or_else(|| Disconnect::ConnectFailed("The timer failed".to_string()))
+}
+
+/// Plays until the connection ends, returning why and how long it lasted.
+fn play<H, S>(
+    websocket: tokio_ws::WebSocketStream<S>,
+    client_state: Arc<Mutex<ClientState>>,
+    handler: Arc<Mutex<H>>,
+) -> impl Future<Item = (Disconnect, Duration), Error = ()>
+where
+    H: Handler + Send + 'static,
+    S: AsyncRead + AsyncWrite,
+{
+    let connected_at = Instant::now();
+
+    // Allow us to build two futures out of this connection - one for send, one for recv.
+    let (sink, stream) = websocket.split();
+
+    let game_loop = build_game_loop(sink, client_state.clone(), handler.clone())
+        .then(|_| Ok(Disconnect::Lost("Failed to send to the server".to_string())));
+    let state_updater = build_state_updater(stream, client_state, handler.clone());
+
+    // Return a future that will finish when either one of the two futures finish.
+    state_updater
+        .select(game_loop)
+        .map(move |(disconnect, _)| {
+            handler.lock().unwrap().on_disconnect(&disconnect);
+            (disconnect, connected_at.elapsed())
+        })
+        .map_err(|_| ())
+}
+
+/// Connects once and plays for as long as the connection lasts.
+fn session<H>(
+    url: Url,
+    connector: Option<tokio_tls::TlsConnector>,
+    timeout: Duration,
+    client_state: Arc<Mutex<ClientState>>,
+    handler: Arc<Mutex<H>>,
+) -> Box<dyn Future<Item = (Disconnect, Duration), Error = ()> + Send>
+where
+    H: Handler + Send + 'static,
+{
+    let not_connected = |disconnect| future::ok((disconnect, Duration::from_secs(0)));
+
+    match connector {
+        None => {
+            let connect = tokio_ws::connect_async(url).map_err(handshake_error);
+
+            Box::new(Timeout::new(connect, timeout).map_err(timeout_error).then(move |result| {
+                match result {
+                    Ok((websocket, _)) => Either::A(play(websocket, client_state, handler)),
+                    Err(disconnect) => Either::B(not_connected(disconnect)),
+                }
+            }))
+        },
+        // connect_async only trusts the system certificates, so do the TLS
+        // handshake ourselves and hand the stream over.
+        Some(connector) => {
+            let domain = match url.host_str() {
+                Some(domain) => domain.to_string(),
+                None => {
+                    let disconnect = Disconnect::ConnectFailed(format!("No host in {}", url));
+                    return Box::new(not_connected(disconnect));
+                },
+            };
+            let port = url.port_or_known_default().unwrap_or(443);
+            let addr = match (domain.as_str(), port).to_socket_addrs().map(|mut addrs| addrs.next())
+            {
+                Ok(Some(addr)) => addr,
+                Ok(None) => {
+                    let disconnect =
+                        Disconnect::ConnectFailed(format!("No address for {}", domain));
+                    return Box::new(not_connected(disconnect));
+                },
+                Err(e) => {
+                    let disconnect =
+                        Disconnect::ConnectFailed(format!("Failed to resolve {}: {}", domain, e));
+                    return Box::new(not_connected(disconnect));
+                },
+            };
+
+            let connect = tokio::net::TcpStream::connect(&addr)
+                .map_err(|e| Disconnect::ConnectFailed(e.to_string()))
+                .and_then(move |socket| {
+                    connector
+                        .connect(&domain, socket)
+                        .map_err(|e| Disconnect::ConnectFailed(e.to_string()))
+                })
+                .and_then(move |socket| {
+                    tokio_ws::client_async(url, socket).map_err(handshake_error)
+                });
+
+            Box::new(Timeout::new(connect, timeout).map_err(timeout_error).then(move |result| {
+                match result {
+                    Ok((websocket, _)) => Either::A(play(websocket, client_state, handler)),
+                    Err(disconnect) => Either::B(not_connected(disconnect)),
+                }
+            }))
+        },
+    }
 }
 
 /// Begin the client-side game loop, using the provided struct that implements `Handler`
 /// to act on behalf of the player.
+///
+/// The server to connect to comes from the environment and command line, see
+/// `ClientConfig::from_env_and_args`. It's `ws://localhost:8080` by default.
 pub fn run<H>(key: &str, name: &str, handler: H) -> Result<(), Error>
 where
     H: Handler + Send + 'static,
 {
-    let host = env::var("SERVER_HOST").unwrap_or("192.168.0.199".into());
-    let url = Url::parse(&format!(
-        "wss://{}/socket?key={}&name={}",
-        host,
-        key,
-        utf8_percent_encode(name, DEFAULT_ENCODE_SET).to_string()
-    ))?;
+    run_with_config(&ClientConfig::from_env_and_args()?, key, name, handler)
+}
+
+/// Like `run`, connecting as set up in `config`.
+///
+/// When the connection drops, the same `handler` plays on after reconnecting
+/// with a growing delay. An error is returned once reconnecting is pointless,
+/// because the API key was refused or the bot was kicked or banned, or when
+/// `config.reconnect.max_attempts` have failed in a row.
+pub fn run_with_config<H>(
+    config: &ClientConfig,
+    key: &str,
+    name: &str,
+    handler: H,
+) -> Result<(), Error>
+where
+    H: Handler + Send + 'static,
+{
+    let url = config.url(key, name)?;
+    let timeout = config.connect_timeout;
+
+    let connector = if url.scheme() == "wss" { custom_tls_connector(&config.tls)? } else { None };
+    let connector = connector.map(tokio_tls::TlsConnector::from);
 
     let client_state =
         Arc::new(Mutex::new(ClientState { id: 0, game_state: GameState::default() }));
+    let handler = Arc::new(Mutex::new(handler));
 
-    let client = tokio_ws::connect_async(url)
-        .and_then(move |(websocket, _)| {
-            // Allow us to build two futures out of this connection - one for send, one for recv.
-            let (sink, stream) = websocket.split();
+    let client = future::loop_fn(Backoff::new(config.reconnect.clone()), move |mut backoff| {
+        // The server sends a fresh ID and state on every connection
+        *client_state.lock().unwrap() = ClientState { id: 0, game_state: GameState::default() };
 
-            let game_loop = build_game_loop(sink, client_state.clone(), handler);
-            let state_updater = build_state_updater(stream, client_state);
+        session(url.clone(), connector.clone(), timeout, client_state.clone(), handler.clone())
+            .and_then(move |(disconnect, connected_for)| {
+                eprintln!("{}", disconnect);
+                if connected_for >= STABLE_CONNECTION {
+                    backoff.reset();
+                }
 
-            // Return a future that will finish when either one of the two futures finish.
-            state_updater.select(game_loop).then(|_| Ok(()))
-        })
-        .map_err(log_err);
+                let delay = if disconnect.is_fatal() { None } else { backoff.next_delay() };
+                let delay = match delay {
+                    Some(delay) => delay,
+                    None if disconnect.is_fatal() => {
+                        return Either::A(future::ok(Loop::Break(Err(disconnect.into()))));
+                    },
+                    None if !backoff.options.enabled => {
+                        return Either::A(future::ok(Loop::Break(Ok(()))));
+                    },
+                    None => {
+                        let error = format_err!("Giving up after {} attempts", backoff.attempts);
+                        return Either::A(future::ok(Loop::Break(Err(error))));
+                    },
+                };
+
+                eprintln!("Reconnecting in {:.1}s", delay.as_secs_f64());
+                Either::B(
+                    Delay::new(Instant::now() + delay).then(move |_| Ok(Loop::Continue(backoff))),
+                )
+            })
+    });
 
-    tokio::run(client);
-    Ok(())
+    tokio::runtime::Runtime::new()?
+        .block_on(client)
+        .map_err(|_| format_err!("The client runtime failed"))?
+}
+
+/// A TLS connector for servers with a self-signed certificate or one from a
+/// private CA. Returns `None` when the system certificates will do.
+fn custom_tls_connector(tls: &TlsOptions) -> Result<Option<native_tls::TlsConnector>, Error> {
+    if tls.ca_cert.is_none() && !tls.accept_invalid_certs {
+        return Ok(None);
+    }
+
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(ref path) = tls.ca_cert {
+        let pem = std::fs::read(path)
+            .map_err(|e| format_err!("Failed to read {}: {}", path.display(), e))?;
+        builder.add_root_certificate(native_tls::Certificate::from_pem(&pem)?);
+    }
+    if tls.accept_invalid_certs {
+        builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(Some(builder.build()?))
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::config::Scheme;
+    use std::env;
+
+    fn from_args(args: &[&str]) -> Result<ClientConfig, Error> {
+        Ok(ClientConfig::builder().args(args.iter().map(|arg| arg.to_string()))?.build())
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn args_take_inline_and_separate_values() {
+        let inline = from_args(&["--host=example.com", "--port=9000", "--room=arena"]).unwrap();
+        let separate =
+            from_args(&["--host", "example.com", "--port", "9000", "--room", "arena"]).unwrap();
+
+        for config in [inline, separate] {
+            assert_eq!(config.host, "example.com");
+            assert_eq!(config.port, Some(9000));
+            assert_eq!(config.room, Some("arena".to_string()));
+        }
+    }
+
+    #[test]
+    fn args_leave_other_flags_to_the_bot() {
+        let config = from_args(&["--aggressive", "--host", "example.com", "extra"]).unwrap();
+        assert_eq!(config.host, "example.com");
+    }
+
+    #[test]
+    fn args_need_valid_values() {
+        assert!(from_args(&["--port"]).is_err());
+        assert!(from_args(&["--port=http"]).is_err());
+        assert!(from_args(&["--scheme", "http"]).is_err());
+        assert!(from_args(&["--query", "ranked"]).is_err());
+    }
+
+    #[test]
+    fn args_add_query_parameters() {
+        let config =
+            from_args(&["--query", "a=b", "--query=c=d=e", "--queue", "ranked", "--query", "a=x"])
+                .unwrap();
+
+        // Setting a parameter again replaces it
+        assert_eq!(config.query, query(&[("c", "d=e"), ("queue", "ranked"), ("a", "x")]));
+    }
+
+    #[test]
+    fn server_takes_a_whole_url() {
+        let config = ClientConfig::builder().server("ws://localhost:9000/game").unwrap().build();
+        assert_eq!(config.scheme, Scheme::Ws);
+        assert_eq!(config.host, "localhost");
+        assert_eq!(config.port, Some(9000));
+        assert_eq!(config.path, "/game");
+    }
+
+    #[test]
+    fn server_defaults_to_wss_and_keeps_the_path() {
+        let config = ClientConfig::builder().server("tokyo.example.com").unwrap().build();
+        assert_eq!(config.scheme, Scheme::Wss);
+        assert_eq!(config.host, "tokyo.example.com");
+        assert_eq!(config.port, None);
+        assert_eq!(config.path, "/socket");
+    }
+
+    #[test]
+    fn server_host_without_a_scheme_uses_wss() {
+        // The only test reading the environment, so it can't race another
+        env::set_var("SERVER_HOST", "tokyo.example.com:4443");
+        let config = ClientConfig::builder().env();
+        env::remove_var("SERVER_HOST");
+
+        let url = config.unwrap().build().url("key", "bot").unwrap();
+        assert_eq!(url.as_str(), "wss://tokyo.example.com:4443/socket?key=key&name=bot");
+    }
+
+    #[test]
+    fn url_has_the_key_name_room_and_query() {
+        let config = ClientConfig::builder().room("arena").query("queue", "ranked").build();
+        let url = config.url("key", "my bot").unwrap();
+
+        assert_eq!(
+            url.as_str(),
+            "ws://localhost:8080/socket?key=key&name=my+bot&room=arena&queue=ranked"
+        );
+    }
+
+    fn backoff(max_attempts: Option<u32>) -> Backoff {
+        Backoff::new(ReconnectOptions {
+            enabled: true,
+            min_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_attempts,
+        })
+    }
+
+    fn closed(code: u16) -> Disconnect {
+        Disconnect::Closed { code, reason: String::new() }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_delay() {
+        let mut backoff = backoff(None);
+
+        // Up to half of every delay is shaved off at random
+        for &full in [100, 200, 400, 800, 1000, 1000].iter() {
+            let full = Duration::from_millis(full);
+            let delay = backoff.next_delay().unwrap();
+            assert!(delay >= full / 2 && delay <= full, "{:?} isn't close to {:?}", delay, full);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay().unwrap() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_gives_up_after_max_attempts() {
+        let mut backoff = backoff(Some(3));
+        for _ in 0..3 {
+            assert!(backoff.next_delay().is_some());
+        }
+        assert_eq!(backoff.next_delay(), None);
+
+        backoff.reset();
+        assert!(backoff.next_delay().is_some());
+    }
+
+    #[test]
+    fn backoff_never_retries_when_disabled() {
+        let mut backoff = backoff(None);
+        backoff.options.enabled = false;
+        assert_eq!(backoff.next_delay(), None);
+    }
+
+    #[test]
+    fn fatal_disconnects() {
+        // Kicked, banned or replaced by another client
+        assert!(closed(CLOSE_NORMAL).is_fatal());
+        assert!(closed(CLOSE_POLICY).is_fatal());
+        assert!(Disconnect::Rejected(403).is_fatal());
+
+        // Server restarts, rate limits and network trouble pass
+        assert!(!closed(1001).is_fatal());
+        assert!(!closed(CLOSE_NO_STATUS).is_fatal());
+        assert!(!Disconnect::Rejected(429).is_fatal());
+        assert!(!Disconnect::Rejected(503).is_fatal());
+        assert!(!Disconnect::Lost("reset".to_string()).is_fatal());
+        assert!(!Disconnect::ConnectFailed("refused".to_string()).is_fatal());
+    }
+}
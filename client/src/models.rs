@@ -21,13 +21,13 @@ pub const PLAYER_MAX_THROTTLE: f32 = 1.0;
 // Send commands more frequently than this interval, and consequences.
 pub const MIN_COMMAND_INTERVAL: Duration = Duration::from_millis(50);
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct GameConfig {
     pub bound_x: f32,
     pub bound_y: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "e", content = "data")]
 pub enum GameCommand {
     #[serde(rename = "rotate")]
@@ -40,7 +40,7 @@ pub enum GameCommand {
     Fire, // Fire at the current angle.
 }
 
-#[derive(Debug, Serialize, Deserialize, Message)]
+#[derive(Clone, Debug, Serialize, Deserialize, Message)]
 #[serde(tag = "e", content = "data")]
 pub enum ServerToClient {
     #[serde(rename = "id")]
@@ -51,6 +51,38 @@ pub enum ServerToClient {
 
     #[serde(rename = "teamnames")]
     TeamNames(HashMap<u32, String>), // Send the game state to the client
+
+    #[serde(rename = "teamcolors")]
+    TeamColors(HashMap<u32, String>), // Ship colors of the teams which picked one
+
+    #[serde(rename = "shutdown")]
+    Shutdown(String), // The server is going down and will close the connection
+
+    #[serde(rename = "queue")]
+    Queue(QueueStatus), // Where the client is in the matchmaking queue
+
+    #[serde(rename = "match")]
+    Match(MatchAssignment), // The client was put in a match, its ID and game states follow
+
+    #[serde(rename = "warning")]
+    Warning(String), // The client is about to be penalized, e.g. for sending commands too fast
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueueStatus {
+    /// 1 for the next bot to be matched.
+    pub position: usize,
+    /// How many bots are waiting in total.
+    pub queued: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MatchAssignment {
+    pub room: String,
+    /// Everyone playing the match, including the client.
+    pub team_names: Vec<String>,
+    /// How long the match lasts before everyone goes back to the queue.
+    pub duration_secs: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -79,7 +111,14 @@ pub struct BulletState {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DeadPlayer {
+    /// Deprecated, use `respawn_in`: when the player would be back if the
+    /// game ran at normal speed from now on. It's still sent for older bots
+    /// and will be removed in a future version.
     pub respawn: SystemTime,
+    /// Game time left until the player is back. It stands still while the
+    /// game is paused and runs faster or slower with the time scale.
+    #[serde(default)]
+    pub respawn_in: Duration,
     pub player: PlayerState,
     pub killer: u32
 }
@@ -108,6 +147,20 @@ pub struct GameState {
     pub dead: Vec<DeadPlayer>,
     pub bullets: Vec<BulletState>,
     pub scoreboard: HashMap<u32, u32>,
+    /// The number of simulation steps since the server started. One per
+    /// state, or `time_scale` rounded up per state when sped up.
+    #[serde(default)]
+    pub tick: u64,
+    /// Whether the server has frozen the game loop.
+    #[serde(default)]
+    pub paused: bool,
+    /// How fast the game runs compared to real time, 1.0 being real time.
+    #[serde(default = "default_time_scale")]
+    pub time_scale: f32,
+}
+
+fn default_time_scale() -> f32 {
+    1.0
 }
 
 impl PlayerState {
@@ -175,7 +228,7 @@ impl Item {
 
 impl GameState {
     pub fn new(bounds: (f32, f32)) -> Self {
-        Self { bounds, ..Default::default() }
+        Self { bounds, time_scale: default_time_scale(), ..Default::default() }
     }
 }
 
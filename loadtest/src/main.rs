@@ -0,0 +1,319 @@
+//! Opens lots of bot connections to a tokyo-rs server, adding more step by
+//! step, and reports how state delivery holds up at each step.
+
+#[macro_use]
+extern crate serde_derive;
+
+mod stats;
+
+use crate::stats::{Stats, StepReport};
+use failure::{format_err, Error};
+use futures::{future, stream, Future, Sink, Stream};
+use rand::Rng;
+use std::{
+    env,
+    f32::consts::PI,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::timer::Interval;
+use tokio_tungstenite::{self as tokio_ws, tungstenite::Message};
+use tokyo::models::GameCommand;
+use url::Url;
+
+// How often every bot measures its round trip time
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "Usage: tokyo-loadtest [options]
+
+Options:
+    --url <url>           Server to connect to [default: ws://localhost:8080]
+    --bots <n>            Bots to connect in total [default: 100]
+    --step <n>            Bots added per step [default: 25]
+    --step-secs <secs>    How long every step lasts [default: 10]
+    --rate <n>            Commands per second per bot, 0 for none [default: 10]
+    --script <path>       JSON lines of commands to cycle through instead of
+                          random ones, e.g. {\"e\":\"fire\"}
+    --key-prefix <prefix> Bots use the API keys <prefix>-0, <prefix>-1, ...
+                          [default: loadtest]
+    --tick-rate <n>       The tick rate the server should keep up [default: 30]";
+
+#[derive(Debug, Clone)]
+struct Options {
+    url: Url,
+    bots: usize,
+    step: usize,
+    step_secs: u64,
+    rate: f64,
+    script: Option<Vec<GameCommand>>,
+    key_prefix: String,
+    tick_rate: f64,
+}
+
+// Only the tick of a state matters, so skip parsing the rest
+#[derive(Deserialize)]
+struct Frame {
+    e: String,
+    data: TickOnly,
+}
+
+#[derive(Deserialize)]
+struct TickOnly {
+    tick: u64,
+    #[serde(default)]
+    time_scale: f32,
+}
+
+fn parse<T: FromStr>(name: &str, value: Option<String>) -> Result<T, Error> {
+    let value = value.ok_or_else(|| format_err!("{} needs a value", name))?;
+    value.parse().map_err(|_| format_err!("Invalid value for {}: {}", name, value))
+}
+
+fn load_script(path: &str) -> Result<Vec<GameCommand>, Error> {
+    let script = std::fs::read_to_string(path)
+        .map_err(|e| format_err!("Failed to read script {}: {}", path, e))?;
+    let commands = script
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| format_err!("Invalid command {}: {}", line, e))
+        })
+        .collect::<Result<Vec<GameCommand>, Error>>()?;
+
+    if commands.is_empty() {
+        return Err(format_err!("Script {} has no commands", path));
+    }
+
+    Ok(commands)
+}
+
+fn parse_args() -> Result<Options, Error> {
+    let mut options = Options {
+        url: Url::parse("ws://localhost:8080")?,
+        bots: 100,
+        step: 25,
+        step_secs: 10,
+        rate: 10.0,
+        script: None,
+        key_prefix: "loadtest".to_string(),
+        tick_rate: 30.0,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--url" => options.url = parse(&arg, args.next())?,
+            "--bots" => options.bots = parse(&arg, args.next())?,
+            "--step" => options.step = parse(&arg, args.next())?,
+            "--step-secs" => options.step_secs = parse(&arg, args.next())?,
+            "--rate" => options.rate = parse(&arg, args.next())?,
+            "--script" => options.script = Some(load_script(&parse::<String>(&arg, args.next())?)?),
+            "--key-prefix" => options.key_prefix = parse(&arg, args.next())?,
+            "--tick-rate" => options.tick_rate = parse(&arg, args.next())?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            },
+            _ => return Err(format_err!("Unknown argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    if options.url.scheme() != "ws" && options.url.scheme() != "wss" {
+        return Err(format_err!("--url must be a ws:// or wss:// URL"));
+    }
+    if options.bots == 0 || options.step == 0 || options.step_secs == 0 {
+        return Err(format_err!("--bots, --step and --step-secs must be more than 0"));
+    }
+    if options.tick_rate <= 0.0 || options.rate < 0.0 {
+        return Err(format_err!("--tick-rate must be positive and --rate can't be negative"));
+    }
+
+    Ok(options)
+}
+
+fn random_command(rng: &mut impl Rng) -> GameCommand {
+    match rng.gen_range(0, 3) {
+        0 => GameCommand::Rotate(rng.gen_range(0.0, 2.0 * PI)),
+        1 => GameCommand::Throttle(rng.gen_range(0.0, 1.0)),
+        _ => GameCommand::Fire,
+    }
+}
+
+/// The commands of one bot, starting at a random offset so the bots don't
+/// all send at the same instant.
+fn commands(
+    index: usize,
+    options: &Options,
+    stats: Arc<Mutex<Stats>>,
+) -> Box<dyn Stream<Item = Message, Error = ()> + Send> {
+    if options.rate == 0.0 {
+        return Box::new(stream::empty());
+    }
+
+    let period = Duration::from_secs_f64(1.0 / options.rate);
+    let start = Instant::now() + period.mul_f64(rand::thread_rng().gen_range(0.0, 1.0));
+    let script = options.script.clone();
+    let mut position = index;
+
+    let commands = Interval::new(start, period).map_err(|_| ()).map(move |_| {
+        let command = match script {
+            Some(ref script) => {
+                position += 1;
+                script[position % script.len()].clone()
+            },
+            None => random_command(&mut rand::thread_rng()),
+        };
+
+        stats.lock().unwrap().record_command();
+        Message::Text(serde_json::to_string(&command).unwrap())
+    });
+
+    Box::new(commands)
+}
+
+/// Pings carrying the time they were sent, which the server echoes back.
+/// The server reads ping payloads as text, so the time is written out in
+/// decimal.
+fn pings(epoch: Instant) -> impl Stream<Item = Message, Error = ()> {
+    Interval::new(Instant::now() + PING_INTERVAL, PING_INTERVAL).map_err(|_| ()).map(move |_| {
+        let nanos = epoch.elapsed().as_nanos() as u64;
+        Message::Ping(nanos.to_string().into_bytes())
+    })
+}
+
+fn spawn_bot(index: usize, options: Arc<Options>, stats: Arc<Mutex<Stats>>) {
+    let mut url = options.url.join("socket").expect("the server URL was checked up front");
+    let key = format!("{}-{}", options.key_prefix, index);
+    url.query_pairs_mut().append_pair("key", &key).append_pair("name", &key);
+
+    let failed_stats = stats.clone();
+    let bot = tokio_ws::connect_async(url)
+        .map_err(move |e| {
+            eprintln!("Bot {} failed to connect: {}", index, e);
+            failed_stats.lock().unwrap().failed += 1;
+        })
+        .and_then(move |(websocket, _)| {
+            let epoch = {
+                let mut stats = stats.lock().unwrap();
+                stats.connected += 1;
+                stats.epoch()
+            };
+            let (sink, stream) = websocket.split();
+
+            let sender = commands(index, &options, stats.clone())
+                .select(pings(epoch))
+                .forward(sink.sink_map_err(|_| ()))
+                .map(|_| ());
+
+            let receiver_stats = stats.clone();
+            let mut previous: Option<(u64, Instant)> = None;
+            let receiver = stream.map_err(|_| ()).for_each(move |message| {
+                let now = Instant::now();
+                match message {
+                    Message::Text(text) => match serde_json::from_str::<Frame>(&text) {
+                        Ok(ref frame) if frame.e == "state" => {
+                            let tick = frame.data.tick;
+                            let time_scale = frame.data.time_scale;
+                            receiver_stats
+                                .lock()
+                                .unwrap()
+                                .record_state(tick, time_scale, now, previous);
+                            if previous.is_none_or(|(previous_tick, _)| tick > previous_tick) {
+                                previous = Some((tick, now));
+                            }
+                        },
+                        _ => {},
+                    },
+                    Message::Pong(payload) => {
+                        let sent = String::from_utf8(payload).ok().and_then(|s| s.parse().ok());
+                        if let Some(sent) = sent.map(Duration::from_nanos) {
+                            let rtt = (now - epoch).checked_sub(sent).unwrap_or_default();
+                            receiver_stats.lock().unwrap().record_rtt(rtt);
+                        }
+                    },
+                    _ => {},
+                }
+
+                Ok(())
+            });
+
+            receiver.select(sender).then(move |_| {
+                eprintln!("Bot {} disconnected", index);
+                stats.lock().unwrap().connected -= 1;
+                Ok(())
+            })
+        });
+
+    tokio::spawn(bot);
+}
+
+fn spawn_bots(range: std::ops::Range<usize>, options: &Arc<Options>, stats: &Arc<Mutex<Stats>>) {
+    for index in range {
+        spawn_bot(index, options.clone(), stats.clone());
+    }
+}
+
+fn print_summary(reports: &[StepReport], tick_rate: f64) {
+    println!();
+    let degraded = reports
+        .iter()
+        .map(|report| (report, report.degradation(tick_rate)))
+        .find(|(_, reasons)| !reasons.is_empty());
+
+    match degraded {
+        Some((report, reasons)) => {
+            println!("The server degraded at {} bots: {}", report.bots, reasons.join(", "))
+        },
+        None => match reports.last() {
+            Some(report) => println!("No degradation up to {} bots", report.bots),
+            None => println!("Nothing was measured"),
+        },
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let options = Arc::new(parse_args()?);
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    let steps = options.bots.div_ceil(options.step);
+    let step_duration = Duration::from_secs(options.step_secs);
+
+    println!(
+        "Connecting {} bots to {}, {} more every {} seconds",
+        options.bots, options.url, options.step, options.step_secs
+    );
+    println!("Times are in milliseconds\n");
+    println!("{}", StepReport::header());
+
+    let driver = {
+        let options = options.clone();
+        let stats = stats.clone();
+
+        future::lazy(move || {
+            spawn_bots(0..options.step.min(options.bots), &options, &stats);
+
+            let mut step = 0;
+            Interval::new(Instant::now() + step_duration, step_duration)
+                .take(steps as u64)
+                .map_err(|e| format_err!("Timer failed: {}", e))
+                .for_each(move |_| {
+                    let started = ((step + 1) * options.step).min(options.bots);
+                    let report = stats.lock().unwrap().finish_step(started);
+                    println!("{}", report.row());
+
+                    step += 1;
+                    let next = ((step + 1) * options.step).min(options.bots);
+                    spawn_bots(started..next, &options, &stats);
+
+                    Ok(())
+                })
+        })
+    };
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(driver)?;
+
+    print_summary(&stats.lock().unwrap().reports, options.tick_rate);
+
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// Ticks whose first arrival is kept around to measure the fan-out skew
+const TRACKED_TICKS: u64 = 300;
+
+// A step counts as degraded past any of these
+const MIN_TICK_RATE_RATIO: f64 = 0.95;
+const MAX_DROPPED_PERCENT: f64 = 1.0;
+const MAX_SKEW_TICKS: f64 = 2.0;
+const MAX_RTT_MS: f64 = 100.0;
+
+/// Samples from the current step.
+#[derive(Debug, Default)]
+struct Window {
+    /// States the server sent, counted once however many bots got them.
+    frames: u64,
+    states: u64,
+    missed: u64,
+    commands: u64,
+    intervals_ms: Vec<f64>,
+    skews_ms: Vec<f64>,
+    rtts_ms: Vec<f64>,
+}
+
+/// Everything measured while a number of bots were connected.
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    pub bots: usize,
+    pub connected: usize,
+    pub failed: usize,
+    /// States the server sent per second, going by the tick numbers the bots
+    /// received.
+    pub tick_rate: f64,
+    pub states_per_bot: f64,
+    pub commands_per_sec: f64,
+    /// Ticks the bots never got a state for.
+    pub dropped_percent: f64,
+    pub interval_p50_ms: f64,
+    pub interval_p99_ms: f64,
+    /// Standard deviation of the time between two states.
+    pub jitter_ms: f64,
+    /// How much later than the first bot the others got the same state.
+    pub skew_p50_ms: f64,
+    pub skew_p99_ms: f64,
+    pub rtt_p50_ms: f64,
+    pub rtt_p99_ms: f64,
+}
+
+impl StepReport {
+    pub fn header() -> String {
+        format!(
+            "{:>6} {:>6} {:>6} {:>8} {:>9} {:>8} {:>8} {:>17} {:>8} {:>17} {:>17}",
+            "bots",
+            "conn",
+            "fail",
+            "ticks/s",
+            "states/s",
+            "cmds/s",
+            "dropped",
+            "interval p50/p99",
+            "jitter",
+            "skew p50/p99",
+            "rtt p50/p99"
+        )
+    }
+
+    pub fn row(&self) -> String {
+        format!(
+            "{:>6} {:>6} {:>6} {:>8.1} {:>9.1} {:>8.1} {:>7.2}% {:>8.1}/{:<8.1} {:>8.1} {:>8.1}/{:<8.1} {:>8.1}/{:<8.1}",
+            self.bots,
+            self.connected,
+            self.failed,
+            self.tick_rate,
+            self.states_per_bot,
+            self.commands_per_sec,
+            self.dropped_percent,
+            self.interval_p50_ms,
+            self.interval_p99_ms,
+            self.jitter_ms,
+            self.skew_p50_ms,
+            self.skew_p99_ms,
+            self.rtt_p50_ms,
+            self.rtt_p99_ms
+        )
+    }
+
+    /// Why this step counts as degraded, if it does.
+    pub fn degradation(&self, expected_tick_rate: f64) -> Vec<String> {
+        let tick_ms = 1000.0 / expected_tick_rate;
+        let mut reasons = vec![];
+
+        if self.tick_rate < expected_tick_rate * MIN_TICK_RATE_RATIO {
+            reasons.push(format!(
+                "ticking at {:.1}/s instead of {:.1}/s",
+                self.tick_rate, expected_tick_rate
+            ));
+        }
+        if self.dropped_percent > MAX_DROPPED_PERCENT {
+            reasons.push(format!("{:.2}% of the states dropped", self.dropped_percent));
+        }
+        if self.skew_p99_ms > tick_ms * MAX_SKEW_TICKS {
+            reasons.push(format!("p99 skew of {:.1} ms", self.skew_p99_ms));
+        }
+        if self.rtt_p99_ms > MAX_RTT_MS {
+            reasons.push(format!("p99 round trip of {:.1} ms", self.rtt_p99_ms));
+        }
+        if self.failed > 0 {
+            reasons.push(format!("{} connections failed", self.failed));
+        }
+
+        reasons
+    }
+}
+
+/// Shared by every bot connection of the load test.
+#[derive(Debug)]
+pub struct Stats {
+    epoch: Instant,
+    pub connected: usize,
+    pub failed: usize,
+    window: Window,
+    window_start: Instant,
+    latest_tick: Option<u64>,
+    first_arrivals: HashMap<u64, Instant>,
+    pub reports: Vec<StepReport>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        let now = Instant::now();
+        Stats {
+            epoch: now,
+            connected: 0,
+            failed: 0,
+            window: Window::default(),
+            window_start: now,
+            latest_tick: None,
+            first_arrivals: HashMap::new(),
+            reports: vec![],
+        }
+    }
+
+    /// The instant ping payloads are measured from.
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+
+    pub fn record_command(&mut self) {
+        self.window.commands += 1;
+    }
+
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        self.window.rtts_ms.push(as_ms(rtt));
+    }
+
+    /// Records a state arriving at a bot, which got its previous one at
+    /// `previous`, if any. `time_scale` is the one sent with the state, as a
+    /// sped up game moves `tick` by more than one per state.
+    pub fn record_state(
+        &mut self,
+        tick: u64,
+        time_scale: f32,
+        now: Instant,
+        previous: Option<(u64, Instant)>,
+    ) {
+        // A paused game sends the same tick over and over
+        if let Some((previous_tick, _)) = previous {
+            if tick <= previous_tick {
+                return;
+            }
+        }
+
+        self.window.states += 1;
+        if let Some((previous_tick, previous_arrival)) = previous {
+            self.window.missed += states_between(previous_tick, tick, time_scale).saturating_sub(1);
+            self.window.intervals_ms.push(as_ms(now - previous_arrival));
+        }
+
+        if self.latest_tick.is_none_or(|latest| tick > latest) {
+            if let Some(latest) = self.latest_tick {
+                self.window.frames += states_between(latest, tick, time_scale).max(1);
+            }
+            self.latest_tick = Some(tick);
+            let oldest = tick.saturating_sub(TRACKED_TICKS);
+            self.first_arrivals.retain(|&t, _| t >= oldest);
+        }
+
+        let first = *self.first_arrivals.entry(tick).or_insert(now);
+        self.window.skews_ms.push(as_ms(now - first));
+    }
+
+    /// Closes the current step with `bots` bots started so far.
+    pub fn finish_step(&mut self, bots: usize) -> StepReport {
+        let now = Instant::now();
+        let elapsed = (now - self.window_start).as_secs_f64().max(0.001);
+        let window = std::mem::take(&mut self.window);
+
+        let seen = window.states + window.missed;
+        let mean_interval = mean(&window.intervals_ms);
+        let variance = window.intervals_ms.iter().map(|i| (i - mean_interval).powi(2)).sum::<f64>()
+            / window.intervals_ms.len().max(1) as f64;
+
+        let report = StepReport {
+            bots,
+            connected: self.connected,
+            failed: self.failed,
+            tick_rate: window.frames as f64 / elapsed,
+            states_per_bot: window.states as f64 / elapsed / self.connected.max(1) as f64,
+            commands_per_sec: window.commands as f64 / elapsed,
+            dropped_percent: if seen == 0 {
+                0.0
+            } else {
+                window.missed as f64 * 100.0 / seen as f64
+            },
+            interval_p50_ms: percentile(&window.intervals_ms, 0.5),
+            interval_p99_ms: percentile(&window.intervals_ms, 0.99),
+            jitter_ms: variance.sqrt(),
+            skew_p50_ms: percentile(&window.skews_ms, 0.5),
+            skew_p99_ms: percentile(&window.skews_ms, 0.99),
+            rtt_p50_ms: percentile(&window.rtts_ms, 0.5),
+            rtt_p99_ms: percentile(&window.rtts_ms, 0.99),
+        };
+
+        self.window_start = now;
+        self.reports.push(report.clone());
+
+        report
+    }
+}
+
+/// How many states the server sent to get from one tick to another. Each
+/// state moves `tick` by `time_scale` rounded up, at least one.
+fn states_between(from: u64, to: u64, time_scale: f32) -> u64 {
+    let ticks_per_state = (time_scale.ceil() as u64).max(1);
+    ((to - from) as f64 / ticks_per_state as f64).round() as u64
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+}
+
+fn percentile(samples: &[f64], p: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
@@ -0,0 +1,351 @@
+use crate::config::SharedConfig;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// How many penalties and rejections the admin API gets to see
+const MAX_RECENT_EVENTS: usize = 100;
+
+/// What a bot gets for a strike. The first strike is a warning, the second a
+/// mute and every one after that a kick and a timed ban.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Penalty {
+    Warn,
+    Mute,
+    Ban,
+}
+
+impl Penalty {
+    fn for_strike(strikes: u32) -> Penalty {
+        match strikes {
+            0 | 1 => Penalty::Warn,
+            2 => Penalty::Mute,
+            _ => Penalty::Ban,
+        }
+    }
+}
+
+/// Why a bot wasn't allowed to connect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    Banned { remaining: Duration },
+    TooManyKeyConnections,
+    TooManyIpConnections,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Banned { remaining } => write!(
+                f,
+                "This API key is banned for another {} seconds",
+                remaining.as_secs().max(1)
+            ),
+            Rejection::TooManyKeyConnections => {
+                write!(f, "Too many connections with this API key")
+            },
+            Rejection::TooManyIpConnections => write!(f, "Too many connections from this IP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AbuseEvent {
+    Penalty { penalty: Penalty, strikes: u32 },
+    Rejected { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AbuseRecord {
+    pub time: f64,
+    pub api_key: String,
+    pub ip: Option<String>,
+    #[serde(flatten)]
+    pub event: AbuseEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyStatus {
+    pub api_key: String,
+    pub connections: usize,
+    pub strikes: u32,
+    pub muted_secs: Option<u64>,
+    pub banned_secs: Option<u64>,
+}
+
+/// Everything the admin API reports about abuse.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbuseStatus {
+    pub connections_per_ip: BTreeMap<String, usize>,
+    /// Keys which are connected or have strikes against them.
+    pub keys: Vec<KeyStatus>,
+    /// The latest penalties and rejected connections, oldest first.
+    pub recent: Vec<AbuseRecord>,
+}
+
+#[derive(Debug, Default)]
+struct KeyRecord {
+    connections: usize,
+    // Rate limited commands since `window_start`
+    violations: u32,
+    window_start: Option<Instant>,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    muted_until: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl KeyRecord {
+    // Whether there's nothing left worth remembering about the key
+    fn is_clean(&self, now: Instant) -> bool {
+        self.connections == 0
+            && self.strikes == 0
+            && self.muted_until.is_none_or(|until| until <= now)
+            && self.banned_until.is_none_or(|until| until <= now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct GuardState {
+    keys: HashMap<String, KeyRecord>,
+    ips: HashMap<String, usize>,
+    recent: VecDeque<AbuseRecord>,
+}
+
+impl GuardState {
+    fn report(&mut self, api_key: &str, ip: Option<&str>, event: AbuseEvent) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs_f64())
+            .unwrap_or(0.0);
+
+        if self.recent.len() == MAX_RECENT_EVENTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(AbuseRecord {
+            time,
+            api_key: api_key.to_string(),
+            ip: ip.map(|ip| ip.to_string()),
+            event,
+        });
+    }
+}
+
+/// Connection caps and escalating penalties for bots which go over their
+/// command budget, shared by every socket. Strikes, mutes and bans follow
+/// the API key across reconnects, but are forgotten on restart.
+#[derive(Debug, Clone)]
+pub struct Guard {
+    config: SharedConfig,
+    state: Arc<Mutex<GuardState>>,
+}
+
+impl Guard {
+    pub fn new(config: SharedConfig) -> Guard {
+        Guard { config, state: Arc::new(Mutex::new(GuardState::default())) }
+    }
+
+    /// Takes up a connection slot for the key and IP, which is given back
+    /// when the permit is dropped.
+    pub fn connect(&self, api_key: &str, ip: Option<&str>) -> Result<ConnectionPermit, Rejection> {
+        let config = self.config.get();
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("guard lock should never be poisoned");
+
+        let key_connections = state.keys.get(api_key).map_or(0, |record| record.connections);
+        let ip_connections = ip.and_then(|ip| state.ips.get(ip)).cloned().unwrap_or(0);
+        let banned_until = state.keys.get(api_key).and_then(|record| record.banned_until);
+
+        let rejection = match banned_until {
+            Some(until) if until > now => Some(Rejection::Banned { remaining: until - now }),
+            _ if config.abuse.max_connections_per_key > 0
+                && key_connections >= config.abuse.max_connections_per_key =>
+            {
+                Some(Rejection::TooManyKeyConnections)
+            },
+            _ if config.abuse.max_connections_per_ip > 0
+                && ip_connections >= config.abuse.max_connections_per_ip =>
+            {
+                Some(Rejection::TooManyIpConnections)
+            },
+            _ => None,
+        };
+
+        if let Some(rejection) = rejection {
+            info!("rejecting API key {} from {:?}: {}", api_key, ip, rejection);
+            state.report(api_key, ip, AbuseEvent::Rejected { reason: rejection.to_string() });
+            return Err(rejection);
+        }
+
+        state.keys.entry(api_key.to_string()).or_default().connections += 1;
+        if let Some(ip) = ip {
+            *state.ips.entry(ip.to_string()).or_insert(0) += 1;
+        }
+
+        Ok(ConnectionPermit {
+            guard: self.clone(),
+            api_key: api_key.to_string(),
+            ip: ip.map(|ip| ip.to_string()),
+        })
+    }
+
+    pub fn commands_per_sec(&self, api_key: &str) -> u32 {
+        self.config.get().abuse.commands_per_sec(api_key)
+    }
+
+    pub fn is_muted(&self, api_key: &str) -> bool {
+        let state = self.state.lock().expect("guard lock should never be poisoned");
+        state
+            .keys
+            .get(api_key)
+            .and_then(|record| record.muted_until)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// Counts a rate limited command. Returns the penalty if it earned the
+    /// key a strike, which the caller carries out.
+    pub fn violation(&self, api_key: &str, ip: Option<&str>) -> Option<Penalty> {
+        let config = self.config.get();
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("guard lock should never be poisoned");
+        let record = state.keys.entry(api_key.to_string()).or_default();
+
+        let window_over = record
+            .window_start
+            .is_none_or(|start| now.duration_since(start) > config.abuse.strike_window());
+        if window_over {
+            record.window_start = Some(now);
+            record.violations = 0;
+        }
+        record.violations += 1;
+
+        if record.violations < config.abuse.violations_per_strike.max(1) {
+            return None;
+        }
+
+        let decayed = record
+            .last_strike
+            .is_some_and(|last| now.duration_since(last) > config.abuse.strike_decay());
+        if decayed {
+            record.strikes = 0;
+        }
+
+        record.window_start = None;
+        record.strikes += 1;
+        record.last_strike = Some(now);
+
+        let penalty = Penalty::for_strike(record.strikes);
+        match penalty {
+            Penalty::Warn => {},
+            Penalty::Mute => record.muted_until = Some(now + config.abuse.mute_duration()),
+            Penalty::Ban => record.banned_until = Some(now + config.abuse.ban_duration()),
+        }
+
+        let strikes = record.strikes;
+        warn!("API key {} earned strike {}: {:?}", api_key, strikes, penalty);
+        state.report(api_key, ip, AbuseEvent::Penalty { penalty, strikes });
+
+        Some(penalty)
+    }
+
+    /// Clears the strikes, mute and timed ban of a key.
+    pub fn pardon(&self, api_key: &str) {
+        let mut state = self.state.lock().expect("guard lock should never be poisoned");
+        if let Some(record) = state.keys.get_mut(api_key) {
+            record.violations = 0;
+            record.window_start = None;
+            record.strikes = 0;
+            record.last_strike = None;
+            record.muted_until = None;
+            record.banned_until = None;
+        }
+    }
+
+    pub fn status(&self) -> AbuseStatus {
+        let config = self.config.get();
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("guard lock should never be poisoned");
+
+        // Forget the strikes which have decayed while we're at it
+        for record in state.keys.values_mut() {
+            if record
+                .last_strike
+                .is_some_and(|last| now.duration_since(last) > config.abuse.strike_decay())
+            {
+                record.strikes = 0;
+            }
+        }
+        state.keys.retain(|_, record| !record.is_clean(now));
+
+        let remaining_secs = |until: Option<Instant>| {
+            until.filter(|&until| until > now).map(|until| (until - now).as_secs())
+        };
+        let mut keys: Vec<KeyStatus> = state
+            .keys
+            .iter()
+            .map(|(api_key, record)| KeyStatus {
+                api_key: api_key.clone(),
+                connections: record.connections,
+                strikes: record.strikes,
+                muted_secs: remaining_secs(record.muted_until),
+                banned_secs: remaining_secs(record.banned_until),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.api_key.cmp(&b.api_key));
+
+        AbuseStatus {
+            connections_per_ip: state.ips.iter().map(|(ip, &count)| (ip.clone(), count)).collect(),
+            keys,
+            recent: state.recent.iter().cloned().collect(),
+        }
+    }
+
+    fn disconnect(&self, api_key: &str, ip: Option<&str>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("guard lock should never be poisoned");
+
+        let clean = match state.keys.get_mut(api_key) {
+            Some(record) => {
+                record.connections = record.connections.saturating_sub(1);
+                record.is_clean(now)
+            },
+            None => false,
+        };
+        if clean {
+            state.keys.remove(api_key);
+        }
+
+        if let Some(ip) = ip {
+            let remaining = match state.ips.get_mut(ip) {
+                Some(count) => {
+                    *count = count.saturating_sub(1);
+                    *count
+                },
+                None => return,
+            };
+            if remaining == 0 {
+                state.ips.remove(ip);
+            }
+        }
+    }
+}
+
+/// A connection slot taken with `Guard::connect`, held by the socket actor
+/// for as long as it lives.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    guard: Guard,
+    api_key: String,
+    ip: Option<String>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.guard.disconnect(&self.api_key, self.ip.as_deref());
+    }
+}
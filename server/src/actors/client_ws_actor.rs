@@ -1,30 +1,189 @@
 use crate::{
-    actors::GameActor,
+    abuse::{ConnectionPermit, Guard, Penalty},
+    actors::{
+        game_actor::SocketEvent,
+        matchmaker_actor::{QueueEvent, QueueUpdate},
+        GameActor, MatchmakerActor,
+    },
+    metrics::Metrics,
     models::messages::{ClientStop, PlayerGameCommand},
     AppState,
 };
 use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, StreamHandler};
 use actix_web::ws::{self, CloseCode, CloseReason};
 use ratelimit_meter::{DirectRateLimiter, GCRA};
-use tokyo::models::ServerToClient;
+use std::{
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokyo::models::{MatchAssignment, QueueStatus, ServerToClient};
 
-const ACTIONS_PER_SECOND: u32 = 22;
+// How often we ping the client to measure its round trip time
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug)]
 pub struct ClientWsActor {
-    game_addr: Addr<GameActor>,
+    // The game the bot plays in, which a queued bot doesn't have
+    game_addr: Option<Addr<GameActor>>,
+    matchmaker_addr: Option<Addr<MatchmakerActor>>,
     api_key: String,
     team_name: String,
+    color: Option<String>,
+    ip: Option<String>,
     rate_limiter: DirectRateLimiter<GCRA>,
+    guard: Guard,
+    // Gives the connection slot back when the actor is dropped
+    _permit: ConnectionPermit,
+    last_ping: Instant,
+    outbound_queue: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
+}
+
+/// Where a bot goes once it's connected.
+#[derive(Debug, Clone)]
+pub enum Lobby {
+    /// Straight into the main game.
+    Game(Addr<GameActor>),
+    /// Into the matchmaking queue, to play matchmade matches.
+    Queue(Addr<MatchmakerActor>),
+}
+
+/// Who is on the other end of a bot socket, and the connection slot the
+/// guard gave them.
+#[derive(Debug)]
+pub struct BotConnection {
+    pub api_key: String,
+    pub team_name: String,
+    pub color: Option<String>,
+    pub ip: Option<String>,
+    pub guard: Guard,
+    pub permit: ConnectionPermit,
+}
+
+/// The address of a `ClientWsActor`, along with a count of the messages sent
+/// through it which the actor hasn't written to its socket yet.
+#[derive(Debug, Clone)]
+pub struct ClientOutbox {
+    pub addr: Addr<ClientWsActor>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl ClientOutbox {
+    pub fn send(&self, msg: ServerToClient) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.addr.do_send(msg);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+impl PartialEq for ClientOutbox {
+    fn eq(&self, other: &ClientOutbox) -> bool {
+        self.addr == other.addr
+    }
+}
+
+impl Eq for ClientOutbox {}
+
+impl Hash for ClientOutbox {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr.hash(state);
+    }
 }
 
 impl ClientWsActor {
-    pub fn new(game_addr: Addr<GameActor>, api_key: String, team_name: String) -> ClientWsActor {
+    pub fn new(lobby: Lobby, connection: BotConnection, metrics: Arc<Metrics>) -> ClientWsActor {
+        let BotConnection { api_key, team_name, color, ip, guard, permit } = connection;
         let rate_limiter = DirectRateLimiter::<GCRA>::per_second(
-            std::num::NonZeroU32::new(ACTIONS_PER_SECOND).unwrap(),
+            std::num::NonZeroU32::new(guard.commands_per_sec(&api_key).max(1)).unwrap(),
         );
 
-        ClientWsActor { game_addr, api_key, team_name, rate_limiter }
+        let (game_addr, matchmaker_addr) = match lobby {
+            Lobby::Game(game_addr) => (Some(game_addr), None),
+            Lobby::Queue(matchmaker_addr) => (None, Some(matchmaker_addr)),
+        };
+
+        ClientWsActor {
+            game_addr,
+            matchmaker_addr,
+            api_key,
+            team_name,
+            color,
+            ip,
+            rate_limiter,
+            guard,
+            _permit: permit,
+            last_ping: Instant::now(),
+            outbound_queue: Arc::new(AtomicUsize::new(0)),
+            metrics,
+        }
+    }
+
+    fn outbox(&self, ctx: &mut <Self as Actor>::Context) -> ClientOutbox {
+        ClientOutbox { addr: ctx.address(), queued: self.outbound_queue.clone() }
+    }
+
+    fn join_game(&self, ctx: &mut <Self as Actor>::Context) {
+        if let Some(ref game_addr) = self.game_addr {
+            game_addr.do_send(SocketEvent::Join {
+                api_key: self.api_key.clone(),
+                team_name: self.team_name.clone(),
+                color: self.color.clone(),
+                ip: self.ip.clone(),
+                outbox: self.outbox(ctx),
+            });
+        }
+    }
+
+    fn leave_game(&self, ctx: &mut <Self as Actor>::Context) {
+        if let Some(ref game_addr) = self.game_addr {
+            game_addr.do_send(SocketEvent::Leave(self.api_key.clone(), ctx.address()));
+        }
+    }
+
+    fn send_to_client(&self, msg: &ServerToClient, ctx: &mut <Self as Actor>::Context) {
+        let start = Instant::now();
+        let text = serde_json::to_string(msg).unwrap();
+        self.metrics.record_serialization(start.elapsed());
+
+        ctx.text(text);
+        self.metrics.messages_sent_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn penalize(&self, penalty: Penalty, ctx: &mut <Self as Actor>::Context) {
+        self.metrics.penalties_total.fetch_add(1, Ordering::Relaxed);
+        let config = ctx.state().config.get();
+
+        match penalty {
+            Penalty::Warn => {
+                let warning = format!(
+                    "Slow down, your budget is {} commands per second. Keep going over it and \
+                     you'll be muted, then banned",
+                    config.abuse.commands_per_sec(&self.api_key)
+                );
+                self.send_to_client(&ServerToClient::Warning(warning), ctx);
+            },
+            Penalty::Mute => {
+                let warning = format!(
+                    "Your commands are ignored for {} seconds for sending them too fast",
+                    config.abuse.mute_secs
+                );
+                self.send_to_client(&ServerToClient::Warning(warning), ctx);
+            },
+            Penalty::Ban => {
+                let reason = format!(
+                    "Banned for {} seconds for sending commands too fast",
+                    config.abuse.ban_secs
+                );
+                ctx.close(Some(CloseReason { code: CloseCode::Policy, description: Some(reason) }));
+            },
+        }
     }
 }
 
@@ -32,19 +191,27 @@ impl Actor for ClientWsActor {
     type Context = ws::WebsocketContext<Self, AppState>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.game_addr.do_send(crate::actors::game_actor::SocketEvent::Join(
-            self.api_key.clone(),
-            self.team_name.clone(),
-            ctx.address(),
-        ));
+        match self.matchmaker_addr {
+            Some(ref matchmaker_addr) => matchmaker_addr.do_send(QueueEvent::Join {
+                api_key: self.api_key.clone(),
+                team_name: self.team_name.clone(),
+                outbox: self.outbox(ctx),
+            }),
+            None => self.join_game(ctx),
+        }
+
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            act.last_ping = Instant::now();
+            ctx.ping("");
+        });
     }
 
     fn stopped(&mut self, ctx: &mut Self::Context) {
         info!("API key {} stopped", self.api_key);
-        self.game_addr.do_send(crate::actors::game_actor::SocketEvent::Leave(
-            self.api_key.clone(),
-            ctx.address(),
-        ));
+        self.leave_game(ctx);
+        if let Some(ref matchmaker_addr) = self.matchmaker_addr {
+            matchmaker_addr.do_send(QueueEvent::Leave(self.api_key.clone(), ctx.address()));
+        }
     }
 }
 
@@ -52,23 +219,43 @@ impl StreamHandler<ws::Message, ws::ProtocolError> for ClientWsActor {
     fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
         match msg {
             ws::Message::Text(cmd) => {
-                if self.rate_limiter.check().is_ok() {
+                self.metrics.messages_received_total.fetch_add(1, Ordering::Relaxed);
+
+                if self.guard.is_muted(&self.api_key) {
+                    // Dropped without counting against the bot any further
+                    self.metrics.rate_limited_total.fetch_add(1, Ordering::Relaxed);
+                } else if self.rate_limiter.check().is_ok() {
                     let cmd_result = serde_json::from_str(&cmd);
 
-                    if let Ok(cmd) = cmd_result {
-                        self.game_addr
-                            .do_send(PlayerGameCommand { api_key: self.api_key.clone(), cmd });
+                    // Commands sent while waiting in the queue go nowhere
+                    if let (Ok(cmd), Some(game_addr)) = (cmd_result, self.game_addr.as_ref()) {
+                        game_addr.do_send(PlayerGameCommand { api_key: self.api_key.clone(), cmd });
                     }
                 } else {
                     warn!("API key {} got rate limited", self.api_key);
+                    self.metrics.rate_limited_total.fetch_add(1, Ordering::Relaxed);
+
+                    let ip = self.ip.as_deref();
+                    if let Some(penalty) = self.guard.violation(&self.api_key, ip) {
+                        self.penalize(penalty, ctx);
+                    }
+                }
+            },
+            ws::Message::Ping(msg) => {
+                ctx.pong(&msg);
+            },
+            ws::Message::Pong(_) => {
+                if let Some(ref game_addr) = self.game_addr {
+                    game_addr.do_send(SocketEvent::Rtt(
+                        self.api_key.clone(),
+                        self.last_ping.elapsed(),
+                        ctx.address(),
+                    ));
                 }
             },
             ws::Message::Close(_) => {
                 info!("API key {} close ws", self.api_key);
-                self.game_addr.do_send(crate::actors::game_actor::SocketEvent::Leave(
-                    self.api_key.clone(),
-                    ctx.address(),
-                ));
+                self.leave_game(ctx);
                 ctx.stop();
             },
             _ => {},
@@ -80,17 +267,38 @@ impl Handler<ServerToClient> for ClientWsActor {
     type Result = ();
 
     fn handle(&mut self, msg: ServerToClient, ctx: &mut Self::Context) {
-        ctx.text(serde_json::to_string(&msg).unwrap());
+        self.outbound_queue.fetch_sub(1, Ordering::Relaxed);
+        self.send_to_client(&msg, ctx);
+    }
+}
+
+impl Handler<QueueUpdate> for ClientWsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueUpdate, ctx: &mut Self::Context) {
+        match msg {
+            QueueUpdate::Queued { position, queued } => {
+                // Back in the queue after a match, whose game is gone by now
+                self.game_addr = None;
+                self.send_to_client(&ServerToClient::Queue(QueueStatus { position, queued }), ctx);
+            },
+            QueueUpdate::Assigned { room, game_addr, team_names, duration } => {
+                info!("API key {} assigned to room {}", self.api_key, room);
+                let assignment =
+                    MatchAssignment { room, team_names, duration_secs: duration.as_secs() };
+                self.send_to_client(&ServerToClient::Match(assignment), ctx);
+
+                self.game_addr = Some(game_addr);
+                self.join_game(ctx);
+            },
+        }
     }
 }
 
 impl Handler<ClientStop> for ClientWsActor {
     type Result = ();
 
-    fn handle(&mut self, _: ClientStop, ctx: &mut Self::Context) {
-        ctx.close(Some(CloseReason {
-            code: CloseCode::Normal,
-            description: Some("The server decided it didn't like you anymore. Or maybe you connected another client with the same API key".to_string())
-        }));
+    fn handle(&mut self, msg: ClientStop, ctx: &mut Self::Context) {
+        ctx.close(Some(CloseReason { code: msg.code, description: Some(msg.reason) }));
     }
 }
@@ -1,52 +1,564 @@
 use crate::{
-    actors::ClientWsActor,
-    game::{Game, TICKS_PER_SECOND},
-    models::messages::{ClientStop, PlayerGameCommand, ServerCommand},
+    actors::{
+        webhook_actor::{Webhook, WebhookEvent, WebhookPlayer, WebhookResult},
+        ClientOutbox, ClientWsActor, SpectatorWsActor, WebhookActor,
+    },
+    audit::{AuditEvent, AuditLog},
+    bots::ServerBot,
+    config::SharedConfig,
+    game::{Game, GameEvent, MAX_TIME_SCALE, MIN_TIME_SCALE, TICKS_PER_SECOND},
+    metrics::Metrics,
+    models::{
+        messages::{
+            ApiFeed, ApiSubscribe, ClientStop, CloseRoom, ConnectionInfo, GamePhase, GameStatus,
+            GetGameConfig, GetMatchScores, GetScoreboard, GetSnapshot, GetStatus, ListConnections,
+            PlayerGameCommand, PlayerStatus, ReloadConfig, ScoreboardEntry, ServerCommand,
+            Shutdown, StateSnapshot, TournamentUpdate,
+        },
+        spectator::{PlayerInfo, PlayerPhase, ServerToSpectator, SpectatorInfo, DEFAULT_ROOM},
+    },
+    ratings::{MatchScore, Ratings},
+    replay::{self, ReplayHeader, ReplayRecord, ReplayRecorder},
+    tournament::TournamentStatus,
 };
-use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
-use futures::sync::oneshot;
+use actix::{
+    Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message, MessageResult, SpawnHandle,
+};
+use actix_web::ws::CloseCode;
+use futures::sync::{mpsc, oneshot};
 use spin_sleep::LoopHelper;
 use std::{
     collections::{HashMap, HashSet},
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 use tokyo::models::*;
 
+// How often spectators get RTTs and player phases
+const SPECTATOR_INFO_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often the HTTP state and scoreboard streams get a snapshot
+const API_STREAM_INTERVAL: Duration = Duration::from_millis(200);
+
+// Kills without dying which are worth a webhook
+const STREAK_MILESTONES: &[u32] = &[3, 5, 10, 15, 20];
+
 #[derive(Debug)]
 pub struct GameActor {
-    connections: HashMap<String, Addr<ClientWsActor>>,
-    spectators: HashSet<Addr<ClientWsActor>>,
+    // The main room, or a matchmade one
+    room: String,
+    connections: HashMap<String, Connection>,
+    spectators: HashSet<Addr<SpectatorWsActor>>,
+    // HTTP clients streaming the state or scoreboard
+    api_streams: Vec<(ApiFeed, mpsc::Sender<String>)>,
+    // The scoreboard the streams got last, so they only get changes
+    streamed_scoreboard: Vec<ScoreboardEntry>,
     team_names: HashMap<u32, String>,
+    team_colors: HashMap<u32, String>,
+    banned_keys: HashSet<String>,
+    // While a tournament match is on, only these API keys may join
+    allowed_keys: Option<HashSet<String>>,
+    tournament: Option<TournamentStatus>,
     cancel_chan: Option<oneshot::Sender<()>>,
+    game_thread: Option<JoinHandle<()>>,
+    shutting_down: bool,
     msg_tx: Sender<GameLoopCommand>,
     msg_rx: Option<Receiver<GameLoopCommand>>,
     player_id_counter: u32,
     api_key_to_player_id: HashMap<String, u32>,
+    // Bots played by the server, which aren't in `api_key_to_player_id`
+    server_bots: Vec<ServerBot>,
+    // API keys whose socket dropped, with the timer that removes their
+    // player once the reconnect grace period is over.
+    disconnected: HashMap<String, SpawnHandle>,
+    config: SharedConfig,
     game_config: GameConfig,
+    metrics: Arc<Metrics>,
+    audit: AuditLog,
+    // The match being recorded, if replays are enabled and anyone is playing
+    replay: Option<ReplayRecorder>,
+    ratings: Ratings,
+    // Final scores of players who left during the current match
+    departed: HashMap<String, MatchScore>,
+    webhooks: Addr<WebhookActor>,
+    // Whether anyone has been killed yet in the current match
+    first_blood: bool,
+    // Kills since their last death, by player ID
+    kill_streaks: HashMap<u32, u32>,
+    // The latest state reported by the game loop
+    latest_state: Option<GameState>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    outbox: ClientOutbox,
+    ip: Option<String>,
+    rtt: Option<Duration>,
 }
 
 #[derive(Debug)]
 pub enum GameLoopCommand {
     PlayerJoined(u32),
     PlayerLeft(u32),
+    PlayerDisconnected(u32),
+    PlayerReconnected(u32),
     GameCommand(u32, GameCommand),
     Reset,
+    Pause,
+    Resume,
+    Step,
+    SetTimeScale(f32),
+    UpdateConfig(GameConfig),
 }
 
 impl GameActor {
-    pub fn new(config: GameConfig) -> GameActor {
+    pub fn new(
+        room: String,
+        config: SharedConfig,
+        metrics: Arc<Metrics>,
+        audit: AuditLog,
+        ratings: Ratings,
+        webhooks: Addr<WebhookActor>,
+    ) -> GameActor {
         let (msg_tx, msg_rx) = channel();
+        let game_config = config.get().game_config;
 
         GameActor {
+            room,
             connections: HashMap::new(),
             spectators: HashSet::new(),
+            api_streams: vec![],
+            streamed_scoreboard: vec![],
             team_names: HashMap::new(),
+            team_colors: HashMap::new(),
+            banned_keys: HashSet::new(),
+            allowed_keys: None,
+            tournament: None,
             cancel_chan: None,
+            game_thread: None,
+            shutting_down: false,
             msg_tx,
             msg_rx: Some(msg_rx),
             player_id_counter: 0,
             api_key_to_player_id: HashMap::new(),
-            game_config: config,
+            server_bots: vec![],
+            disconnected: HashMap::new(),
+            config,
+            game_config,
+            metrics,
+            audit,
+            replay: None,
+            ratings,
+            departed: HashMap::new(),
+            webhooks,
+            first_blood: false,
+            kill_streaks: HashMap::new(),
+            latest_state: None,
+        }
+    }
+
+    /// Only lets these API keys join, for the whole life of the game.
+    pub fn with_allowed_keys(mut self, api_keys: HashSet<String>) -> GameActor {
+        self.allowed_keys = Some(api_keys);
+        self
+    }
+
+    fn tick(&self) -> u64 {
+        self.latest_state.as_ref().map(|state| state.tick).unwrap_or(0)
+    }
+
+    fn record_audit(&mut self, api_key: Option<&str>, event: AuditEvent) {
+        let tick = self.tick();
+        self.audit.record(tick, api_key, event);
+    }
+
+    fn api_key_for_player(&self, player_id: u32) -> Option<&String> {
+        self.api_key_to_player_id.iter().find(|(_, id)| **id == player_id).map(|(key, _)| key)
+    }
+
+    /// Drops the command once the game loop has stopped, as sockets closing
+    /// and commands in flight keep arriving while shutting down.
+    fn send_to_game_loop(&self, cmd: GameLoopCommand) {
+        if self.shutting_down || self.game_thread.is_none() {
+            debug!("The game loop has stopped, dropping {:?}", cmd);
+            return;
+        }
+
+        if let Err(e) = self.msg_tx.send(cmd) {
+            error!("The game loop stopped receiving commands, dropping {:?}", e.0);
+        }
+    }
+
+    fn clients(&self) -> impl Iterator<Item = &ClientOutbox> {
+        self.connections.values().map(|c| &c.outbox)
+    }
+
+    fn snapshot(&self) -> Option<StateSnapshot> {
+        self.latest_state.as_ref().map(|state| StateSnapshot {
+            state: state.clone(),
+            team_names: self.team_names.clone(),
+        })
+    }
+
+    fn scoreboard(&self) -> Vec<ScoreboardEntry> {
+        let state = match self.latest_state {
+            Some(ref state) => state,
+            None => return vec![],
+        };
+
+        let mut scores: Vec<(u32, u32)> = state
+            .players
+            .iter()
+            .map(|player| player.id)
+            .chain(state.dead.iter().map(|dead| dead.player.id))
+            .map(|id| (id, state.scoreboard.get(&id).cloned().unwrap_or(0)))
+            .collect();
+        scores
+            .sort_by(|(a_id, a_score), (b_id, b_score)| b_score.cmp(a_score).then(a_id.cmp(b_id)));
+
+        let mut scoreboard: Vec<ScoreboardEntry> = vec![];
+        for (i, (id, score)) in scores.into_iter().enumerate() {
+            let rank = match scoreboard.last() {
+                Some(previous) if previous.score == score => previous.rank,
+                _ => i + 1,
+            };
+            scoreboard.push(ScoreboardEntry {
+                rank,
+                id,
+                team_name: self.team_names.get(&id).cloned(),
+                score,
+            });
+        }
+
+        scoreboard
+    }
+
+    /// Sends the state to every state stream, and the scoreboard to the
+    /// scoreboard streams if it changed.
+    fn update_api_streams(&mut self) {
+        if self.api_streams.is_empty() {
+            return;
+        }
+
+        let state = self.snapshot().and_then(|snapshot| serde_json::to_string(&snapshot).ok());
+        let scoreboard = self.scoreboard();
+        let scoreboard = if scoreboard != self.streamed_scoreboard {
+            let json = serde_json::to_string(&scoreboard).ok();
+            self.streamed_scoreboard = scoreboard;
+            json
+        } else {
+            None
+        };
+
+        self.api_streams.retain_mut(|(feed, sender)| {
+            let json = match feed {
+                ApiFeed::State => &state,
+                ApiFeed::Scoreboard => &scoreboard,
+            };
+            match json {
+                // A full buffer only means the client is slow
+                Some(json) => {
+                    !matches!(sender.try_send(json.clone()), Err(ref e) if e.is_disconnected())
+                },
+                // Nothing new to send, but clients which went away still
+                // need dropping
+                None => sender.poll_ready().is_ok(),
+            }
+        });
+    }
+
+    fn team_info(&self) -> [ServerToClient; 2] {
+        [
+            ServerToClient::TeamNames(self.team_names.clone()),
+            ServerToClient::TeamColors(self.team_colors.clone()),
+        ]
+    }
+
+    fn broadcast_team_info(&mut self) {
+        let tick = self.tick();
+        self.record_replay(ReplayRecord::TeamNames {
+            tick,
+            team_names: self.team_names.clone(),
+            team_colors: self.team_colors.clone(),
+        });
+
+        for msg in self.team_info().iter() {
+            for outbox in self.clients() {
+                outbox.send(msg.clone());
+            }
+            for spectator in &self.spectators {
+                spectator.do_send(msg.clone());
+            }
+        }
+    }
+
+    fn spectator_info(&self) -> SpectatorInfo {
+        let is_dead = |id: u32| {
+            self.latest_state
+                .as_ref()
+                .is_some_and(|state| state.dead.iter().any(|dead| dead.player.id == id))
+        };
+
+        let bots = self.server_bots.iter().map(|bot| PlayerInfo {
+            id: bot.player_id,
+            team_name: self.team_names.get(&bot.player_id).cloned(),
+            phase: if is_dead(bot.player_id) { PlayerPhase::Dead } else { PlayerPhase::Alive },
+            rtt_ms: None,
+        });
+        let players = self
+            .api_key_to_player_id
+            .iter()
+            .map(|(api_key, &id)| {
+                let phase = if self.disconnected.contains_key(api_key) {
+                    PlayerPhase::Disconnected
+                } else if is_dead(id) {
+                    PlayerPhase::Dead
+                } else {
+                    PlayerPhase::Alive
+                };
+
+                PlayerInfo {
+                    id,
+                    team_name: self.team_names.get(&id).cloned(),
+                    phase,
+                    rtt_ms: self
+                        .connections
+                        .get(api_key)
+                        .and_then(|c| c.rtt)
+                        .map(|rtt| rtt.as_secs_f64() * 1000.0),
+                }
+            })
+            .chain(bots)
+            .collect();
+
+        SpectatorInfo { room: self.room.clone(), spectators: self.spectators.len(), players }
+    }
+
+    fn broadcast_to_spectators(&self, msg: ServerToSpectator) {
+        for spectator in &self.spectators {
+            spectator.do_send(msg.clone());
+        }
+    }
+
+    fn set_team(&mut self, player_id: u32, team_name: String, color: Option<String>) {
+        self.team_names.insert(player_id, team_name);
+        match color {
+            Some(color) => self.team_colors.insert(player_id, color),
+            None => self.team_colors.remove(&player_id),
+        };
+    }
+
+    fn next_player_id(&mut self) -> u32 {
+        let player_id = self.player_id_counter;
+        self.player_id_counter += 1;
+        player_id
+    }
+
+    /// Adds or removes server bots so the game has as many ships as the
+    /// config asks for. Matches restricted to some API keys get none.
+    fn update_server_bots(&mut self) {
+        let config = self.config.get().server_bots;
+        let wanted = if self.allowed_keys.is_some() {
+            0
+        } else {
+            config.count.saturating_sub(self.api_key_to_player_id.len())
+        };
+        if wanted == self.server_bots.len() {
+            return;
+        }
+
+        while self.server_bots.len() > wanted {
+            let bot = self.server_bots.pop().expect("there are more bots than wanted");
+            info!("server bot {} leaves", bot.player_id);
+            self.send_to_game_loop(GameLoopCommand::PlayerLeft(bot.player_id));
+        }
+
+        while self.server_bots.len() < wanted {
+            let bot = ServerBot::new(self.next_player_id(), config.difficulty);
+            info!("server bot {} joins", bot.player_id);
+            self.send_to_game_loop(GameLoopCommand::PlayerJoined(bot.player_id));
+            self.set_team(bot.player_id, bot.team_name(), None);
+            self.server_bots.push(bot);
+        }
+
+        self.broadcast_team_info();
+    }
+
+    fn remove_player(&mut self, api_key: &str) {
+        // The match was already finished when shutting down
+        if self.shutting_down {
+            return;
+        }
+
+        if let Some(player_id) = self.api_key_to_player_id.get(api_key).cloned() {
+            // Their score still counts when the match is over
+            let score = self.match_score(player_id);
+            self.departed.insert(api_key.to_string(), score);
+
+            self.api_key_to_player_id.remove(api_key);
+            self.send_to_game_loop(GameLoopCommand::PlayerLeft(player_id));
+        }
+
+        // Nobody left to play, the match is over. A matchmade room finishes
+        // its match once, when the matchmaker closes it.
+        if self.api_key_to_player_id.is_empty() && self.room == DEFAULT_ROOM {
+            self.finish_match();
+        }
+
+        self.update_server_bots();
+    }
+
+    fn match_score(&self, player_id: u32) -> MatchScore {
+        let score = self
+            .latest_state
+            .as_ref()
+            .and_then(|state| state.scoreboard.get(&player_id))
+            .cloned()
+            .unwrap_or(0);
+        let team_name = self.team_names.get(&player_id).cloned().unwrap_or_default();
+
+        MatchScore { team_name, score }
+    }
+
+    /// The scores of everyone who played in the current match so far.
+    fn match_scores(&self) -> HashMap<String, MatchScore> {
+        let mut scores = self.departed.clone();
+        for (api_key, &player_id) in self.api_key_to_player_id.iter() {
+            scores.insert(api_key.clone(), self.match_score(player_id));
+        }
+
+        scores
+    }
+
+    /// Rates the players on their final scores and closes the replay. Called
+    /// right before the game is reset or everyone has left.
+    fn finish_match(&mut self) {
+        let scores = self.match_scores();
+        self.departed.clear();
+        self.first_blood = false;
+        self.kill_streaks.clear();
+
+        if !scores.is_empty() {
+            let mut results: Vec<WebhookResult> = scores
+                .values()
+                .map(|score| WebhookResult {
+                    team_name: score.team_name.clone(),
+                    score: score.score,
+                })
+                .collect();
+            results.sort_by(|a, b| b.score.cmp(&a.score).then(a.team_name.cmp(&b.team_name)));
+            self.send_webhook(WebhookEvent::MatchEnd { results });
+        }
+
+        match self.ratings.record_match(&scores) {
+            Ok(true) => {
+                info!("Updated ratings for {} players", scores.len());
+                self.broadcast_to_spectators(ServerToSpectator::Ratings(
+                    self.ratings.leaderboard(),
+                ));
+            },
+            Ok(false) => {},
+            Err(e) => error!("Failed to save ratings: {}", e),
+        }
+
+        self.finish_replay();
+    }
+
+    fn send_webhook(&self, event: WebhookEvent) {
+        self.webhooks.do_send(Webhook { room: self.room.clone(), tick: self.tick(), event });
+    }
+
+    fn webhook_player(&self, player_id: u32) -> WebhookPlayer {
+        WebhookPlayer { id: player_id, team_name: self.team_names.get(&player_id).cloned() }
+    }
+
+    fn record_kill(&mut self, killer: u32, victim: u32) {
+        if !self.first_blood {
+            self.first_blood = true;
+            self.send_webhook(WebhookEvent::FirstBlood {
+                killer: self.webhook_player(killer),
+                victim: self.webhook_player(victim),
+            });
+        }
+
+        self.kill_streaks.remove(&victim);
+        if killer != victim {
+            let kills = self.kill_streaks.entry(killer).or_insert(0);
+            *kills += 1;
+            let kills = *kills;
+            if STREAK_MILESTONES.contains(&kills) {
+                self.send_webhook(WebhookEvent::Streak {
+                    player: self.webhook_player(killer),
+                    kills,
+                });
+            }
+        }
+    }
+
+    fn start_replay(&mut self) {
+        let dir = match self.config.get().replay_dir.clone() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let header =
+            ReplayHeader::new(self.game_config, self.team_names.clone(), self.team_colors.clone());
+        match ReplayRecorder::create(&dir, &self.room, &header) {
+            Ok(recorder) => self.replay = Some(recorder),
+            Err(e) => error!("Failed to start recording a replay: {}", e),
+        }
+    }
+
+    fn record_replay(&mut self, record: ReplayRecord) {
+        if let Some(ref mut replay) = self.replay {
+            replay.record(&record);
+        }
+    }
+
+    fn finish_replay(&mut self) {
+        if let Some(replay) = self.replay.take() {
+            replay.finish();
+
+            let config = self.config.get();
+            if let Some(ref dir) = config.replay_dir {
+                if let Err(e) = replay::prune_replays(dir, config.max_replays) {
+                    error!("Failed to delete old replays: {}", e);
+                }
+            }
+        }
+    }
+
+    fn set_game_config(&mut self, config: GameConfig) {
+        self.game_config = config;
+        self.send_to_game_loop(GameLoopCommand::UpdateConfig(config));
+
+        let tick = self.tick();
+        self.record_replay(ReplayRecord::GameConfig { tick, game_config: config });
+    }
+
+    fn stop_game_loop(&mut self) {
+        if let Some(cancel_chan) = self.cancel_chan.take() {
+            let _ = cancel_chan.send(());
+        }
+        // The loop checks the cancel channel every tick, so this won't block
+        // for long.
+        if let Some(game_thread) = self.game_thread.take() {
+            if game_thread.join().is_err() {
+                error!("The game loop panicked");
+            }
+        }
+    }
+
+    fn kick(&mut self, api_key: &str, reason: &str) {
+        if let Some(connection) = self.connections.get(api_key) {
+            info!("kicking API key {}", api_key);
+            connection.outbox.addr.do_send(ClientStop::kicked(reason));
+            self.record_audit(Some(api_key), AuditEvent::Kick { reason: reason.to_string() });
         }
     }
 }
@@ -56,15 +568,22 @@ fn game_loop(
     msg_chan: Receiver<GameLoopCommand>,
     mut cancel_chan: oneshot::Receiver<()>,
     config: GameConfig,
+    metrics: Arc<Metrics>,
 ) {
-    let mut loop_helper = LoopHelper::builder().build_with_target_rate(TICKS_PER_SECOND);
+    let mut loop_helper =
+        LoopHelper::builder().report_interval_s(1.0).build_with_target_rate(TICKS_PER_SECOND);
+    let target_tick_duration = Duration::from_secs_f32(1.0 / TICKS_PER_SECOND);
 
     let mut game = Game::new(config);
+    let mut paused = false;
+    let mut pending_steps = 0u32;
+    let mut time_scale = 1.0f32;
 
     game.init();
 
     loop {
-        loop_helper.loop_start();
+        let tick_duration = loop_helper.loop_start();
+        let tick_start = Instant::now();
 
         match cancel_chan.try_recv() {
             Ok(Some(_)) | Err(_) => {
@@ -82,23 +601,74 @@ fn game_loop(
                 GameLoopCommand::PlayerLeft(id) => {
                     game.player_left(id);
                 },
+                GameLoopCommand::PlayerDisconnected(id) => {
+                    game.player_disconnected(id);
+                },
+                GameLoopCommand::PlayerReconnected(id) => {
+                    game.player_reconnected(id);
+                },
                 GameLoopCommand::GameCommand(id, cmd) => {
                     game.handle_cmd(id, cmd);
                 },
                 GameLoopCommand::Reset => {
                     game.reset();
-                }
+                },
+                GameLoopCommand::Pause => {
+                    paused = true;
+                },
+                GameLoopCommand::Resume => {
+                    paused = false;
+                    pending_steps = 0;
+                },
+                GameLoopCommand::Step => {
+                    pending_steps += 1;
+                },
+                GameLoopCommand::SetTimeScale(scale) => {
+                    time_scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+                },
+                GameLoopCommand::UpdateConfig(config) => {
+                    game.set_config(config);
+                },
             }
         }
 
-        let dt = 1.0 / TICKS_PER_SECOND;
-        game.tick(dt);
+        if !paused {
+            // Split sped up ticks into sub-ticks no longer than a normal one,
+            // so fast bullets don't tunnel through ships.
+            let mut remaining = time_scale;
+            while remaining > 0.0 {
+                let step = remaining.min(1.0);
+                game.tick(step / TICKS_PER_SECOND);
+                remaining -= step;
+            }
+        } else if pending_steps > 0 {
+            pending_steps -= 1;
+            game.tick(1.0 / TICKS_PER_SECOND);
+        }
+
+        game.state.paused = paused;
+        game.state.time_scale = time_scale;
+
+        metrics.players.store(game.state.players.len(), Ordering::Relaxed);
+        metrics.dead_players.store(game.state.dead.len(), Ordering::Relaxed);
+        metrics.bullets.store(game.state.bullets.len(), Ordering::Relaxed);
+        metrics.items.store(game.state.items.len(), Ordering::Relaxed);
+        if let Some(rate) = loop_helper.report_rate() {
+            metrics.set_tick_rate(rate);
+        }
 
         // Send out update packets
 
         // TODO(bschwind) - maybe put the game state behind an Arc
         //                  instead of cloning it
         game_actor.do_send(game.state.clone());
+
+        let events = game.drain_events();
+        if !events.is_empty() {
+            game_actor.do_send(GameEvents { tick: game.state.tick, events });
+        }
+
+        metrics.record_tick(tick_duration, tick_start.elapsed(), target_tick_duration);
         loop_helper.loop_sleep();
     }
 
@@ -109,7 +679,7 @@ impl Actor for GameActor {
     type Context = Context<GameActor>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        info!("Game Actor started!");
+        info!("Game Actor started for room {}", self.room);
         let (cancel_tx, cancel_rx) = oneshot::channel();
         let addr = ctx.address();
 
@@ -118,91 +688,207 @@ impl Actor for GameActor {
         let msg_rx = self.msg_rx.take().unwrap();
 
         let config = self.game_config;
-        std::thread::spawn(move || {
-            game_loop(addr, msg_rx, cancel_rx, config);
+        let metrics = self.metrics.clone();
+        let game_thread = std::thread::spawn(move || {
+            game_loop(addr, msg_rx, cancel_rx, config, metrics);
         });
 
         self.cancel_chan = Some(cancel_tx);
+        self.game_thread = Some(game_thread);
+
+        self.update_server_bots();
+
+        ctx.run_interval(SPECTATOR_INFO_INTERVAL, |act, _ctx| {
+            if !act.spectators.is_empty() {
+                act.broadcast_to_spectators(ServerToSpectator::Info(act.spectator_info()));
+            }
+        });
+        ctx.run_interval(API_STREAM_INTERVAL, |act, _ctx| act.update_api_streams());
     }
 }
 
+#[derive(Debug, Message)]
+pub struct GameEvents {
+    pub tick: u64,
+    pub events: Vec<GameEvent>,
+}
+
 #[derive(Debug, Message)]
 pub enum SocketEvent {
-    Join(String, String, Addr<ClientWsActor>),
+    Join {
+        api_key: String,
+        team_name: String,
+        color: Option<String>,
+        ip: Option<String>,
+        outbox: ClientOutbox,
+    },
     Leave(String, Addr<ClientWsActor>),
+    Rtt(String, Duration, Addr<ClientWsActor>),
+}
+
+#[derive(Debug, Message)]
+pub enum SpectatorEvent {
+    Subscribe(Addr<SpectatorWsActor>),
+    Unsubscribe(Addr<SpectatorWsActor>),
 }
 
 impl Handler<SocketEvent> for GameActor {
     type Result = ();
 
-    fn handle(&mut self, msg: SocketEvent, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: SocketEvent, ctx: &mut Self::Context) {
         match msg {
-            SocketEvent::Join(api_key, team_name, addr) => {
+            SocketEvent::Join { api_key, team_name, color, ip, outbox } => {
                 let key_clone = api_key.clone();
-                let addr_clone = addr.clone();
+                let outbox_clone = outbox.clone();
 
                 info!("person joined - {:?}", api_key);
 
-                if api_key == "SPECTATOR" {
-                    addr.do_send(ServerToClient::TeamNames(self.team_names.clone()));
-                    self.spectators.insert(addr);
+                if self.shutting_down {
+                    outbox.addr.do_send(ClientStop {
+                        code: CloseCode::Away,
+                        reason: "The server is shutting down".to_string(),
+                    });
+                } else if self.banned_keys.contains(&api_key) {
+                    info!("rejecting banned API key {}", api_key);
+                    let reason = "This API key has been banned";
+                    outbox.addr.do_send(ClientStop::kicked(reason));
+                    self.record_audit(
+                        Some(&api_key),
+                        AuditEvent::Rejected { reason: reason.to_string(), ip },
+                    );
+                } else if !self.allowed_keys.as_ref().is_none_or(|keys| keys.contains(&api_key)) {
+                    info!("rejecting unscheduled API key {}", api_key);
+                    let stop = ClientStop::not_scheduled();
+                    self.record_audit(
+                        Some(&api_key),
+                        AuditEvent::Rejected { reason: stop.reason.clone(), ip },
+                    );
+                    outbox.addr.do_send(stop);
                 } else {
-                    let existing_client_opt = self.connections.insert(api_key, addr);
+                    let existing_client_opt = self
+                        .connections
+                        .insert(api_key, Connection { outbox, ip: ip.clone(), rtt: None });
 
                     if let Some(existing_client) = existing_client_opt {
                         info!("kicking out old connection");
-                        existing_client.do_send(ClientStop {});
+                        existing_client.outbox.addr.do_send(ClientStop {
+                            code: CloseCode::Normal,
+                            reason: "The server decided it didn't like you anymore. Or maybe you connected another client with the same API key".to_string(),
+                        });
                     }
 
-                    let player_id =
-                        if let Some(player_id) = self.api_key_to_player_id.get(&key_clone) {
-                            addr_clone.do_send(ServerToClient::Id(*player_id));
-                            *player_id
-                        } else {
-                            // This was the first time this API key connected,
-                            // assign them a player ID and return it
-                            let player_id = self.player_id_counter;
-                            self.player_id_counter += 1;
-                            info!("API key {} gets player ID {}", key_clone, player_id);
+                    let player_id = if let Some(player_id) =
+                        self.api_key_to_player_id.get(&key_clone)
+                    {
+                        if let Some(handle) = self.disconnected.remove(&key_clone) {
+                            // They came back within the grace period
+                            ctx.cancel_future(handle);
+                            self.send_to_game_loop(GameLoopCommand::PlayerReconnected(*player_id));
+                        }
+
+                        outbox_clone.send(ServerToClient::Id(*player_id));
+                        *player_id
+                    } else {
+                        // This was the first time this API key connected,
+                        // assign them a player ID and return it
+                        let player_id = self.next_player_id();
+                        info!("API key {} gets player ID {}", key_clone, player_id);
+
+                        self.api_key_to_player_id.insert(key_clone.clone(), player_id);
 
-                            self.api_key_to_player_id.insert(key_clone, player_id);
+                        self.send_to_game_loop(GameLoopCommand::PlayerJoined(player_id));
 
-                            self.msg_tx
-                                .send(GameLoopCommand::PlayerJoined(player_id))
-                                .expect("The game loop should always be receiving commands");
+                        outbox_clone.send(ServerToClient::Id(player_id));
+                        player_id
+                    };
 
-                            addr_clone.do_send(ServerToClient::Id(player_id));
-                            player_id
-                        };
+                    self.record_audit(
+                        Some(&key_clone),
+                        AuditEvent::Connect { player_id, team_name: team_name.clone(), ip },
+                    );
 
                     // Update team name and broadcast new team names list to all sockets.
-                    self.team_names.insert(player_id, team_name);
-                    for addr in self.connections.values().chain(self.spectators.iter()) {
-                        addr.do_send(ServerToClient::TeamNames(self.team_names.clone()));
-                    }
+                    self.set_team(player_id, team_name, color);
+                    self.broadcast_team_info();
+
+                    // Make room for the new player
+                    self.update_server_bots();
                 }
             },
             SocketEvent::Leave(api_key, addr) => {
-                if api_key == "SPECTATOR" {
-                    self.spectators.remove(&addr);
-                } else {
-                    if let Some(client_addr) = self.connections.get(&api_key) {
-                        if addr == *client_addr {
-                            info!("person left - {:?}", api_key);
-
-                            if let Some(player_id) = self.api_key_to_player_id.get(&api_key) {
-                                self.msg_tx
-                                    .send(GameLoopCommand::PlayerLeft(*player_id))
-                                    .expect("The game loop should always be receiving commands");
-                            }
-
-                            self.api_key_to_player_id.remove(&api_key);
-                            self.connections.remove(&api_key);
+                if let Some(connection) = self.connections.get(&api_key) {
+                    if addr == connection.outbox.addr {
+                        info!("person left - {:?}", api_key);
+                        let ip = self.connections.remove(&api_key).and_then(|c| c.ip);
+
+                        let grace_period = self.config.get().reconnect_grace_period();
+                        let player_id = self.api_key_to_player_id.get(&api_key).cloned();
+
+                        self.record_audit(Some(&api_key), AuditEvent::Disconnect { player_id, ip });
+
+                        match player_id {
+                            Some(player_id) if grace_period > Duration::from_secs(0) => {
+                                self.send_to_game_loop(GameLoopCommand::PlayerDisconnected(
+                                    player_id,
+                                ));
+
+                                let key_clone = api_key.clone();
+                                let handle = ctx.run_later(grace_period, move |act, _ctx| {
+                                    if act.disconnected.remove(&key_clone).is_some() {
+                                        info!("grace period over for {:?}", key_clone);
+                                        act.remove_player(&key_clone);
+                                    }
+                                });
+                                self.disconnected.insert(api_key, handle);
+                            },
+                            _ => self.remove_player(&api_key),
                         }
                     }
                 }
             },
+            SocketEvent::Rtt(api_key, rtt, addr) => {
+                if let Some(connection) = self.connections.get_mut(&api_key) {
+                    if addr == connection.outbox.addr {
+                        connection.rtt = Some(rtt);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Handler<SpectatorEvent> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SpectatorEvent, _ctx: &mut Self::Context) {
+        match msg {
+            SpectatorEvent::Subscribe(addr) => {
+                if self.shutting_down {
+                    addr.do_send(ClientStop {
+                        code: CloseCode::Away,
+                        reason: "The server is shutting down".to_string(),
+                    });
+                    return;
+                }
+
+                info!("spectator joined");
+                self.spectators.insert(addr.clone());
+                for msg in self.team_info().iter() {
+                    addr.do_send(msg.clone());
+                }
+                addr.do_send(ServerToSpectator::Info(self.spectator_info()));
+                addr.do_send(ServerToSpectator::Ratings(self.ratings.leaderboard()));
+                if self.tournament.is_some() {
+                    addr.do_send(ServerToSpectator::Tournament(self.tournament.clone()));
+                }
+            },
+            SpectatorEvent::Unsubscribe(addr) => {
+                info!("spectator left");
+                self.spectators.remove(&addr);
+            },
         }
+
+        self.metrics.spectators.store(self.spectators.len(), Ordering::Relaxed);
     }
 }
 
@@ -210,10 +896,15 @@ impl Handler<PlayerGameCommand> for GameActor {
     type Result = ();
 
     fn handle(&mut self, msg: PlayerGameCommand, _ctx: &mut Self::Context) {
-        if let Some(player_id) = self.api_key_to_player_id.get(&msg.api_key) {
-            self.msg_tx
-                .send(GameLoopCommand::GameCommand(*player_id, msg.cmd))
-                .expect("The game loop should always be receiving commands");
+        if self.shutting_down {
+            return;
+        }
+
+        if let Some(player_id) = self.api_key_to_player_id.get(&msg.api_key).cloned() {
+            let tick = self.tick();
+            self.record_replay(ReplayRecord::Command { tick, player_id, command: msg.cmd.clone() });
+
+            self.send_to_game_loop(GameLoopCommand::GameCommand(player_id, msg.cmd));
         }
     }
 }
@@ -222,8 +913,81 @@ impl Handler<GameState> for GameActor {
     type Result = ();
 
     fn handle(&mut self, msg: GameState, _ctx: &mut Self::Context) {
-        for addr in self.connections.values().chain(self.spectators.iter()) {
-            addr.do_send(ServerToClient::GameState(msg.clone()));
+        for outbox in self.clients() {
+            outbox.send(ServerToClient::GameState(msg.clone()));
+        }
+        for spectator in &self.spectators {
+            spectator.do_send(ServerToClient::GameState(msg.clone()));
+        }
+
+        if self.replay.is_none() && !self.api_key_to_player_id.is_empty() && !self.shutting_down {
+            self.start_replay();
+        }
+        self.record_replay(ReplayRecord::State(msg.clone()));
+
+        if !self.server_bots.is_empty() {
+            let now = Instant::now();
+            let tick = msg.tick;
+            let mut state = ClientState { id: 0, game_state: msg.clone() };
+            let mut commands = vec![];
+            for bot in self.server_bots.iter_mut() {
+                state.id = bot.player_id;
+                if let Some(command) = bot.tick(&state, now) {
+                    commands.push((bot.player_id, command));
+                }
+            }
+
+            for (player_id, command) in commands {
+                self.record_replay(ReplayRecord::Command {
+                    tick,
+                    player_id,
+                    command: command.clone(),
+                });
+                self.send_to_game_loop(GameLoopCommand::GameCommand(player_id, command));
+            }
+        }
+
+        self.latest_state = Some(msg);
+    }
+}
+
+impl Handler<GameEvents> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: GameEvents, _ctx: &mut Self::Context) {
+        self.broadcast_to_spectators(ServerToSpectator::Events(msg.events.clone()));
+
+        for event in msg.events {
+            match event {
+                GameEvent::Kill { killer, victim, bullet_id, killer_position, victim_position } => {
+                    let killer_key = self.api_key_for_player(killer).cloned();
+                    let victim_api_key = self.api_key_for_player(victim).cloned();
+
+                    self.audit.record(
+                        msg.tick,
+                        killer_key.as_deref(),
+                        AuditEvent::Kill {
+                            killer_id: killer,
+                            victim_id: victim,
+                            victim_api_key,
+                            bullet_id,
+                            killer_position,
+                            victim_position,
+                        },
+                    );
+
+                    self.record_kill(killer, victim);
+                },
+                GameEvent::ItemPickup { player, item_id, item_type } => {
+                    let api_key = self.api_key_for_player(player).cloned();
+
+                    self.audit.record(
+                        msg.tick,
+                        api_key.as_deref(),
+                        AuditEvent::ItemPickup { player_id: player, item_id, item_type },
+                    );
+                },
+            }
         }
     }
 }
@@ -231,13 +995,291 @@ impl Handler<GameState> for GameActor {
 impl Handler<ServerCommand> for GameActor {
     type Result = ();
 
-    fn handle(&mut self, msg: ServerCommand, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: ServerCommand, ctx: &mut Self::Context) {
+        if self.shutting_down {
+            info!("ignoring {:?}, the server is shutting down", msg);
+            return;
+        }
+
+        self.record_audit(None, AuditEvent::Admin { command: msg.clone() });
+
         match msg {
             ServerCommand::Reset => {
-                self.msg_tx
-                    .send(GameLoopCommand::Reset)
-                    .expect("The game loop should always be receiving commands");
-            }
+                // The next state starts a new recording
+                self.finish_match();
+                self.send_to_game_loop(GameLoopCommand::Reset);
+            },
+            ServerCommand::Pause => {
+                self.send_to_game_loop(GameLoopCommand::Pause);
+            },
+            ServerCommand::Resume => {
+                self.send_to_game_loop(GameLoopCommand::Resume);
+            },
+            ServerCommand::Step => {
+                self.send_to_game_loop(GameLoopCommand::Step);
+            },
+            ServerCommand::SetTimeScale(scale) => {
+                self.send_to_game_loop(GameLoopCommand::SetTimeScale(scale));
+            },
+            ServerCommand::Kick(api_key) => {
+                self.kick(&api_key, "You have been kicked by an admin");
+            },
+            ServerCommand::Ban(api_key) => {
+                info!("banning API key {}", api_key);
+                self.kick(&api_key, "This API key has been banned");
+                self.banned_keys.insert(api_key);
+            },
+            ServerCommand::Unban(api_key) => {
+                info!("unbanning API key {}", api_key);
+                self.banned_keys.remove(&api_key);
+            },
+            ServerCommand::UpdateTeam(api_key, team_name, color) => {
+                if let Some(player_id) = self.api_key_to_player_id.get(&api_key).cloned() {
+                    self.set_team(player_id, team_name, color);
+                    self.broadcast_team_info();
+                }
+            },
+            ServerCommand::UpdateGameConfig(config) => {
+                info!("updating game config - {:?}", config);
+                self.set_game_config(config);
+            },
+            ServerCommand::StartMatch(api_keys) => {
+                let allowed: HashSet<String> = api_keys.into_iter().collect();
+
+                let unscheduled: Vec<String> = self
+                    .api_key_to_player_id
+                    .keys()
+                    .filter(|api_key| !allowed.contains(*api_key))
+                    .cloned()
+                    .collect();
+
+                // Set first, so server bots don't fill in for the removed
+                // players
+                self.allowed_keys = Some(allowed);
+                self.update_server_bots();
+
+                for api_key in unscheduled {
+                    if let Some(connection) = self.connections.remove(&api_key) {
+                        connection.outbox.addr.do_send(ClientStop::not_scheduled());
+                    }
+                    if let Some(handle) = self.disconnected.remove(&api_key) {
+                        ctx.cancel_future(handle);
+                    }
+                    self.remove_player(&api_key);
+                }
+
+                self.finish_match();
+                self.send_to_game_loop(GameLoopCommand::Reset);
+            },
+            ServerCommand::EndMatch => {
+                // Rate the match before free play starts over
+                self.finish_match();
+                self.send_to_game_loop(GameLoopCommand::Reset);
+
+                self.allowed_keys = None;
+                self.update_server_bots();
+            },
         }
     }
 }
+
+impl Handler<Shutdown> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, ctx: &mut Self::Context) {
+        if self.shutting_down {
+            return;
+        }
+        self.shutting_down = true;
+
+        for (_, handle) in self.disconnected.drain() {
+            ctx.cancel_future(handle);
+        }
+
+        info!("Disconnecting {} clients", self.connections.len() + self.spectators.len());
+        let reason = "The server is shutting down";
+        for outbox in self.clients() {
+            outbox.send(ServerToClient::Shutdown(reason.to_string()));
+            outbox.addr.do_send(ClientStop { code: CloseCode::Away, reason: reason.to_string() });
+        }
+        for spectator in &self.spectators {
+            spectator.do_send(ServerToClient::Shutdown(reason.to_string()));
+            spectator.do_send(ClientStop { code: CloseCode::Away, reason: reason.to_string() });
+        }
+
+        self.stop_game_loop();
+        self.finish_match();
+        self.record_audit(None, AuditEvent::Shutdown);
+        self.audit.flush();
+    }
+}
+
+impl Handler<CloseRoom> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, _: CloseRoom, ctx: &mut Self::Context) {
+        info!("Closing room {}", self.room);
+
+        self.stop_game_loop();
+        self.finish_match();
+        self.broadcast_to_spectators(ServerToSpectator::Closed(self.room.clone()));
+        self.audit.flush();
+
+        ctx.stop();
+    }
+}
+
+impl Handler<ReloadConfig> for GameActor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, _: ReloadConfig, _ctx: &mut Self::Context) -> Self::Result {
+        let result = self.config.reload();
+        self.record_audit(None, AuditEvent::ConfigReloaded { success: result.is_ok() });
+
+        let config = result.map_err(|e| {
+            error!("Failed to reload config: {}", e);
+            e.to_string()
+        })?;
+
+        // API keys and the dev mode flag are read from the shared config on
+        // every connection, so only the game and server bots need updating.
+        self.set_game_config(config.game_config);
+        self.update_server_bots();
+
+        Ok(())
+    }
+}
+
+impl Handler<ListConnections> for GameActor {
+    type Result = MessageResult<ListConnections>;
+
+    fn handle(&mut self, _: ListConnections, _ctx: &mut Self::Context) -> Self::Result {
+        let connections = self
+            .connections
+            .iter()
+            .map(|(api_key, connection)| {
+                let player_id = self.api_key_to_player_id.get(api_key).cloned();
+
+                ConnectionInfo {
+                    api_key: api_key.clone(),
+                    player_id,
+                    team_name: player_id.and_then(|id| self.team_names.get(&id).cloned()),
+                    ip: connection.ip.clone(),
+                    rtt_ms: connection.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    outbound_queue: connection.outbox.queued(),
+                }
+            })
+            .collect();
+
+        MessageResult(connections)
+    }
+}
+
+impl Handler<GetGameConfig> for GameActor {
+    type Result = MessageResult<GetGameConfig>;
+
+    fn handle(&mut self, _: GetGameConfig, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.game_config)
+    }
+}
+
+impl Handler<GetSnapshot> for GameActor {
+    type Result = MessageResult<GetSnapshot>;
+
+    fn handle(&mut self, _: GetSnapshot, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.snapshot())
+    }
+}
+
+impl Handler<GetScoreboard> for GameActor {
+    type Result = MessageResult<GetScoreboard>;
+
+    fn handle(&mut self, _: GetScoreboard, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.scoreboard())
+    }
+}
+
+impl Handler<ApiSubscribe> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ApiSubscribe, _ctx: &mut Self::Context) {
+        let ApiSubscribe { feed, mut sender } = msg;
+
+        // Start with the current snapshot rather than waiting for a change
+        let json = match feed {
+            ApiFeed::State => self.snapshot().and_then(|s| serde_json::to_string(&s).ok()),
+            ApiFeed::Scoreboard => serde_json::to_string(&self.scoreboard()).ok(),
+        };
+        if let Some(json) = json {
+            let _ = sender.try_send(json);
+        }
+
+        self.api_streams.push((feed, sender));
+    }
+}
+
+impl Handler<GetStatus> for GameActor {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _: GetStatus, _ctx: &mut Self::Context) -> Self::Result {
+        let paused = self.latest_state.as_ref().is_some_and(|state| state.paused);
+        let phase = if self.shutting_down {
+            GamePhase::ShuttingDown
+        } else if paused {
+            GamePhase::Paused
+        } else if self.allowed_keys.is_some() {
+            GamePhase::Match
+        } else {
+            GamePhase::FreeForAll
+        };
+
+        let info = self.spectator_info();
+        let mut players: Vec<PlayerStatus> = info
+            .players
+            .into_iter()
+            .map(|player| PlayerStatus {
+                score: self
+                    .latest_state
+                    .as_ref()
+                    .and_then(|state| state.scoreboard.get(&player.id))
+                    .cloned()
+                    .unwrap_or(0),
+                id: player.id,
+                team_name: player.team_name,
+                phase: player.phase,
+            })
+            .collect();
+        players.sort_by(|a, b| b.score.cmp(&a.score).then(a.id.cmp(&b.id)));
+
+        MessageResult(GameStatus {
+            room: info.room,
+            phase,
+            tick: self.tick(),
+            spectators: info.spectators,
+            players,
+        })
+    }
+}
+
+impl Handler<GetMatchScores> for GameActor {
+    type Result = MessageResult<GetMatchScores>;
+
+    fn handle(&mut self, _: GetMatchScores, _ctx: &mut Self::Context) -> Self::Result {
+        let scores = self
+            .match_scores()
+            .into_iter()
+            .map(|(api_key, match_score)| (api_key, match_score.score))
+            .collect();
+
+        MessageResult(scores)
+    }
+}
+
+impl Handler<TournamentUpdate> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: TournamentUpdate, _ctx: &mut Self::Context) {
+        self.tournament = msg.0;
+        self.broadcast_to_spectators(ServerToSpectator::Tournament(self.tournament.clone()));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{
+    actors::{ClientOutbox, ClientWsActor, GameActor, WebhookActor},
+    audit::AuditLog,
+    config::{MatchmakingConfig, SharedConfig},
+    metrics::Metrics,
+    models::messages::{
+        ClientStop, CloseRoom, FindRoom, GetMatchmaking, MatchmakingStatus, RoomInfo, Shutdown,
+    },
+    ratings::Ratings,
+};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, MessageResult};
+use actix_web::ws::CloseCode;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokyo::models::ServerToClient;
+
+/// The `?queue=` value for matchmade matches.
+pub const RANKED_QUEUE: &str = "ranked";
+
+// How often the queue is checked for bots which can be matched
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps bots which connected with `?queue=ranked` in a queue, and starts a
+/// fresh game in its own room for every group of them it can match. When the
+/// match is over, the bots still connected go back to the queue.
+#[derive(Debug)]
+pub struct MatchmakerActor {
+    config: SharedConfig,
+    audit: AuditLog,
+    ratings: Ratings,
+    webhooks: Addr<WebhookActor>,
+    queue: Vec<QueuedBot>,
+    rooms: HashMap<String, Room>,
+    room_counter: u32,
+    shutting_down: bool,
+}
+
+#[derive(Debug)]
+struct Bot {
+    api_key: String,
+    team_name: String,
+    outbox: ClientOutbox,
+}
+
+#[derive(Debug)]
+struct QueuedBot {
+    bot: Bot,
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct Room {
+    game_addr: Addr<GameActor>,
+    // Everyone playing the match, whether they're connected or not
+    api_keys: HashSet<String>,
+    team_names: Vec<String>,
+    // The players currently connected
+    bots: HashMap<String, Bot>,
+    ends_at: Instant,
+}
+
+/// Sent by a `ClientWsActor` connected with `?queue=ranked`.
+#[derive(Debug, Message)]
+pub enum QueueEvent {
+    Join { api_key: String, team_name: String, outbox: ClientOutbox },
+    Leave(String, Addr<ClientWsActor>),
+}
+
+/// Tells a queued bot's socket actor where it's at.
+#[derive(Debug, Message)]
+pub enum QueueUpdate {
+    Queued {
+        position: usize,
+        queued: usize,
+    },
+    Assigned {
+        room: String,
+        game_addr: Addr<GameActor>,
+        team_names: Vec<String>,
+        duration: Duration,
+    },
+}
+
+impl MatchmakerActor {
+    pub fn new(
+        config: SharedConfig,
+        audit: AuditLog,
+        ratings: Ratings,
+        webhooks: Addr<WebhookActor>,
+    ) -> MatchmakerActor {
+        MatchmakerActor {
+            config,
+            audit,
+            ratings,
+            webhooks,
+            queue: vec![],
+            rooms: HashMap::new(),
+            room_counter: 0,
+            shutting_down: false,
+        }
+    }
+
+    fn send_positions(&self) {
+        let queued = self.queue.len();
+        for (i, entry) in self.queue.iter().enumerate() {
+            entry.bot.outbox.addr.do_send(QueueUpdate::Queued { position: i + 1, queued });
+        }
+    }
+
+    fn enqueue(&mut self, bot: Bot) {
+        info!("API key {} joined the {} queue", bot.api_key, RANKED_QUEUE);
+        self.queue.push(QueuedBot { bot, since: Instant::now() });
+    }
+
+    /// Picks the bots of the next match as indices into the queue, if there
+    /// is one to be made. Full matches of similarly rated bots come first,
+    /// then anyone who has waited too long is matched with whoever is
+    /// closest in rating.
+    fn next_match(&self, config: &MatchmakingConfig, now: Instant) -> Option<Vec<usize>> {
+        let match_size = config.match_size.max(2);
+        if self.queue.len() < 2 {
+            return None;
+        }
+
+        let ratings: Vec<f64> =
+            self.queue.iter().map(|entry| self.ratings.rating(&entry.bot.api_key)).collect();
+        let mut by_rating: Vec<usize> = (0..self.queue.len()).collect();
+        by_rating.sort_by(|&a, &b| {
+            ratings[a].partial_cmp(&ratings[b]).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        if by_rating.len() >= match_size {
+            // The queue is in joining order, so of all the groups close
+            // enough in rating, pick the one with the longest waiting bot
+            let full_match = by_rating
+                .windows(match_size)
+                .filter(|group| {
+                    ratings[group[match_size - 1]] - ratings[group[0]] <= config.max_rating_gap
+                })
+                .min_by_key(|group| group.iter().min().cloned());
+            if let Some(group) = full_match {
+                return Some(group.to_vec());
+            }
+        }
+
+        let longest_waiting = &self.queue[0];
+        if now.duration_since(longest_waiting.since) < config.max_wait() {
+            return None;
+        }
+
+        let mut closest: Vec<usize> = (1..self.queue.len()).collect();
+        closest.sort_by(|&a, &b| {
+            let distance = |i: usize| (ratings[i] - ratings[0]).abs();
+            distance(a).partial_cmp(&distance(b)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        closest.truncate(match_size - 1);
+        closest.insert(0, 0);
+
+        Some(closest)
+    }
+
+    fn form_matches(&mut self, ctx: &mut Context<Self>) {
+        let config = self.config.get();
+        let now = Instant::now();
+        let mut formed = false;
+
+        while let Some(mut picked) = self.next_match(&config.matchmaking, now) {
+            // Remove from the back so the other indices stay valid
+            picked.sort_unstable_by(|a, b| b.cmp(a));
+            let bots: Vec<Bot> = picked.into_iter().map(|i| self.queue.remove(i).bot).collect();
+            self.start_match(bots, config.matchmaking.match_duration(), ctx);
+            formed = true;
+        }
+
+        if formed {
+            self.send_positions();
+        }
+    }
+
+    fn start_match(&mut self, bots: Vec<Bot>, duration: Duration, ctx: &mut Context<Self>) {
+        self.room_counter += 1;
+        let room = format!("{}-{}", RANKED_QUEUE, self.room_counter);
+
+        let api_keys: HashSet<String> = bots.iter().map(|bot| bot.api_key.clone()).collect();
+        let team_names: Vec<String> = bots.iter().map(|bot| bot.team_name.clone()).collect();
+        info!("Starting match in room {} between {:?}", room, team_names);
+
+        // Only the main game shows up in the metrics, so every room gets its
+        // own unexported set.
+        let game_addr = GameActor::new(
+            room.clone(),
+            self.config.clone(),
+            Arc::new(Metrics::default()),
+            self.audit.for_room(&room),
+            self.ratings.clone(),
+            self.webhooks.clone(),
+        )
+        .with_allowed_keys(api_keys.clone())
+        .start();
+
+        for bot in bots.iter() {
+            bot.outbox.addr.do_send(QueueUpdate::Assigned {
+                room: room.clone(),
+                game_addr: game_addr.clone(),
+                team_names: team_names.clone(),
+                duration,
+            });
+        }
+
+        let room_clone = room.clone();
+        ctx.run_later(duration, move |act, _ctx| act.close_room(&room_clone));
+
+        self.rooms.insert(
+            room,
+            Room {
+                game_addr,
+                api_keys,
+                team_names,
+                bots: bots.into_iter().map(|bot| (bot.api_key.clone(), bot)).collect(),
+                ends_at: Instant::now() + duration,
+            },
+        );
+    }
+
+    fn close_room(&mut self, room: &str) {
+        let room = match self.rooms.remove(room) {
+            Some(room) => room,
+            None => return,
+        };
+
+        room.game_addr.do_send(CloseRoom);
+
+        // The ratings are updated when the game actor closes the room, which
+        // happens before the next matchmaking pass.
+        for (_, bot) in room.bots {
+            self.enqueue(bot);
+        }
+        self.send_positions();
+    }
+
+    fn room_of(&self, api_key: &str) -> Option<&String> {
+        self.rooms.iter().find(|(_, room)| room.api_keys.contains(api_key)).map(|(name, _)| name)
+    }
+}
+
+impl Actor for MatchmakerActor {
+    type Context = Context<MatchmakerActor>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(MATCHMAKING_INTERVAL, |act, ctx| {
+            if !act.shutting_down {
+                act.form_matches(ctx);
+            }
+        });
+    }
+}
+
+impl Handler<QueueEvent> for MatchmakerActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueEvent, _ctx: &mut Self::Context) {
+        match msg {
+            QueueEvent::Join { api_key, team_name, outbox } => {
+                if self.shutting_down {
+                    outbox.addr.do_send(ClientStop {
+                        code: CloseCode::Away,
+                        reason: "The server is shutting down".to_string(),
+                    });
+                    return;
+                }
+
+                let bot = Bot { api_key: api_key.clone(), team_name, outbox };
+
+                // A bot which reconnects during its match goes straight back
+                // in, its game kicks out the old connection.
+                if let Some(name) = self.room_of(&api_key).cloned() {
+                    let room = self.rooms.get_mut(&name).expect("room_of only returns open rooms");
+                    bot.outbox.addr.do_send(QueueUpdate::Assigned {
+                        room: name.clone(),
+                        game_addr: room.game_addr.clone(),
+                        team_names: room.team_names.clone(),
+                        duration: room.ends_at.saturating_duration_since(Instant::now()),
+                    });
+                    room.bots.insert(api_key, bot);
+                    return;
+                }
+
+                match self.queue.iter_mut().find(|entry| entry.bot.api_key == api_key) {
+                    Some(entry) => {
+                        // Same as the game, the newest connection wins but
+                        // keeps its place in the queue
+                        entry.bot.outbox.addr.do_send(ClientStop {
+                            code: CloseCode::Normal,
+                            reason: "Another client connected with the same API key".to_string(),
+                        });
+                        entry.bot = bot;
+                    },
+                    None => self.enqueue(bot),
+                }
+            },
+            QueueEvent::Leave(api_key, addr) => {
+                self.queue.retain(|entry| entry.bot.outbox.addr != addr);
+
+                let mut emptied = vec![];
+                for (name, room) in self.rooms.iter_mut() {
+                    if room.bots.get(&api_key).is_some_and(|bot| bot.outbox.addr == addr) {
+                        room.bots.remove(&api_key);
+                        if room.bots.is_empty() {
+                            emptied.push(name.clone());
+                        }
+                    }
+                }
+
+                // Nobody left to play, no need to wait for the time to run out
+                for room in emptied {
+                    self.close_room(&room);
+                }
+            },
+        }
+
+        self.send_positions();
+    }
+}
+
+impl Handler<FindRoom> for MatchmakerActor {
+    type Result = MessageResult<FindRoom>;
+
+    fn handle(&mut self, msg: FindRoom, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.rooms.get(&msg.0).map(|room| room.game_addr.clone()))
+    }
+}
+
+impl Handler<GetMatchmaking> for MatchmakerActor {
+    type Result = MessageResult<GetMatchmaking>;
+
+    fn handle(&mut self, _: GetMatchmaking, _ctx: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .iter()
+            .map(|(name, room)| RoomInfo {
+                room: name.clone(),
+                team_names: room.team_names.clone(),
+                remaining_secs: room.ends_at.saturating_duration_since(now).as_secs(),
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.room.cmp(&b.room));
+
+        MessageResult(MatchmakingStatus {
+            queued: self.queue.iter().map(|entry| entry.bot.team_name.clone()).collect(),
+            rooms,
+        })
+    }
+}
+
+impl Handler<Shutdown> for MatchmakerActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, _ctx: &mut Self::Context) {
+        if self.shutting_down {
+            return;
+        }
+        self.shutting_down = true;
+
+        let reason = "The server is shutting down";
+        for entry in self.queue.drain(..) {
+            entry.bot.outbox.send(ServerToClient::Shutdown(reason.to_string()));
+            entry
+                .bot
+                .outbox
+                .addr
+                .do_send(ClientStop { code: CloseCode::Away, reason: reason.to_string() });
+        }
+
+        // Each game disconnects its own players and finishes its match
+        for room in self.rooms.values() {
+            room.game_addr.do_send(Shutdown);
+        }
+    }
+}
@@ -1,5 +1,17 @@
 pub mod client_ws_actor;
 pub mod game_actor;
+pub mod matchmaker_actor;
+pub mod replay_ws_actor;
+pub mod signal_actor;
+pub mod spectator_ws_actor;
+pub mod tournament_actor;
+pub mod webhook_actor;
 
-pub use client_ws_actor::ClientWsActor;
+pub use client_ws_actor::{BotConnection, ClientOutbox, ClientWsActor, Lobby};
 pub use game_actor::GameActor;
+pub use matchmaker_actor::MatchmakerActor;
+pub use replay_ws_actor::ReplayWsActor;
+pub use signal_actor::SignalActor;
+pub use spectator_ws_actor::SpectatorWsActor;
+pub use tournament_actor::TournamentActor;
+pub use webhook_actor::WebhookActor;
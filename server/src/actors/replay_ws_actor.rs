@@ -0,0 +1,161 @@
+use crate::{
+    game::{MAX_TIME_SCALE, MIN_TIME_SCALE, TICKS_PER_SECOND},
+    models::spectator::{ReplayCommand, ReplayStatus, ServerToSpectator},
+    replay::Replay,
+    AppState,
+};
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::ws;
+use serde::Serialize;
+use std::time::Duration;
+use tokyo::models::ServerToClient;
+
+// How often the browser is told where playback is at
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Plays a recorded match back to a browser, in the same format `/spectate`
+/// uses for live games. The replay must have at least one state.
+#[derive(Debug)]
+pub struct ReplayWsActor {
+    id: String,
+    replay: Replay,
+    position: usize,
+    playing: bool,
+    speed: f32,
+    // Fraction of a frame left over between playback ticks at odd speeds
+    frame_remainder: f32,
+    // The tick of the team names the browser has last been sent
+    teams_tick: Option<u64>,
+}
+
+impl ReplayWsActor {
+    pub fn new(id: String, replay: Replay) -> ReplayWsActor {
+        ReplayWsActor {
+            id,
+            replay,
+            position: 0,
+            playing: true,
+            speed: 1.0,
+            frame_remainder: 0.0,
+            teams_tick: None,
+        }
+    }
+
+    fn send_json<T: Serialize>(&self, msg: &T, ctx: &mut <Self as Actor>::Context) {
+        ctx.text(serde_json::to_string(msg).unwrap());
+    }
+
+    fn last_position(&self) -> usize {
+        self.replay.states.len() - 1
+    }
+
+    fn send_status(&self, ctx: &mut <Self as Actor>::Context) {
+        let states = &self.replay.states;
+        let status = ReplayStatus {
+            id: self.id.clone(),
+            tick: states[self.position].tick,
+            first_tick: states[0].tick,
+            last_tick: states[self.last_position()].tick,
+            playing: self.playing,
+            speed: self.speed,
+        };
+
+        self.send_json(&ServerToSpectator::Replay(status), ctx);
+    }
+
+    fn send_frame(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let state = &self.replay.states[self.position];
+
+        let teams = self.replay.teams_at(state.tick);
+        if self.teams_tick != Some(teams.tick) {
+            self.send_json(&ServerToClient::TeamNames(teams.team_names.clone()), ctx);
+            self.send_json(&ServerToClient::TeamColors(teams.team_colors.clone()), ctx);
+            self.teams_tick = Some(teams.tick);
+        }
+
+        self.send_json(&ServerToClient::GameState(state.clone()), ctx);
+    }
+
+    fn advance(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if !self.playing {
+            return;
+        }
+
+        self.frame_remainder += self.speed;
+        let frames = self.frame_remainder.floor();
+        self.frame_remainder -= frames;
+
+        if frames >= 1.0 {
+            self.position = (self.position + frames as usize).min(self.last_position());
+            self.send_frame(ctx);
+        }
+
+        if self.position == self.last_position() {
+            self.playing = false;
+            self.send_status(ctx);
+        }
+    }
+
+    fn handle_command(&mut self, cmd: ReplayCommand, ctx: &mut <Self as Actor>::Context) {
+        match cmd {
+            ReplayCommand::Play => {
+                if self.position == self.last_position() {
+                    self.position = 0;
+                    self.send_frame(ctx);
+                }
+                self.playing = true;
+            },
+            ReplayCommand::Pause => {
+                self.playing = false;
+            },
+            ReplayCommand::Seek(tick) => {
+                self.position = self.replay.position_of(tick);
+                self.frame_remainder = 0.0;
+                self.send_frame(ctx);
+            },
+            ReplayCommand::Speed(speed) => {
+                self.speed = speed.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+            },
+        }
+
+        self.send_status(ctx);
+    }
+}
+
+impl Actor for ReplayWsActor {
+    type Context = ws::WebsocketContext<Self, AppState>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.send_frame(ctx);
+        self.send_status(ctx);
+
+        ctx.run_interval(Duration::from_secs_f32(1.0 / TICKS_PER_SECOND), |act, ctx| {
+            act.advance(ctx);
+        });
+        ctx.run_interval(STATUS_INTERVAL, |act, ctx| {
+            if act.playing {
+                act.send_status(ctx);
+            }
+        });
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for ReplayWsActor {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(cmd) => self.handle_command(cmd, ctx),
+                Err(e) => {
+                    self.send_json(&ServerToSpectator::Error(e.to_string()), ctx);
+                },
+            },
+            ws::Message::Ping(msg) => {
+                ctx.pong(&msg);
+            },
+            ws::Message::Close(_) => {
+                ctx.stop();
+            },
+            _ => {},
+        }
+    }
+}
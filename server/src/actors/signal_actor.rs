@@ -0,0 +1,122 @@
+use crate::{
+    actors::{GameActor, MatchmakerActor},
+    models::messages::{ReloadConfig, Shutdown},
+};
+use actix::{
+    actors::signal::{ProcessSignals, Signal, SignalType, Subscribe},
+    Actor, Addr, Arbiter, AsyncContext, Context, Handler, System,
+};
+use actix_net::server::{PauseServer, Server, StopServer};
+use futures::Future;
+use std::{fmt, time::Duration};
+
+// The server exits after this long, even if the shutdown isn't done yet
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shuts the server down gracefully when sent `Shutdown`. With
+/// `handle_signals` it also listens for process signals: SIGHUP reloads the
+/// config file, SIGINT, SIGTERM and SIGQUIT shut the server down.
+pub struct SignalActor {
+    game_addr: Addr<GameActor>,
+    matchmaker_addr: Addr<MatchmakerActor>,
+    server_addr: Addr<Server>,
+    handle_signals: bool,
+    shutting_down: bool,
+}
+
+// The HTTP server actor has no Debug of its own
+impl fmt::Debug for SignalActor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignalActor")
+            .field("game_addr", &self.game_addr)
+            .field("matchmaker_addr", &self.matchmaker_addr)
+            .field("handle_signals", &self.handle_signals)
+            .field("shutting_down", &self.shutting_down)
+            .finish()
+    }
+}
+
+impl SignalActor {
+    pub fn new(
+        game_addr: Addr<GameActor>,
+        matchmaker_addr: Addr<MatchmakerActor>,
+        server_addr: Addr<Server>,
+        handle_signals: bool,
+    ) -> SignalActor {
+        SignalActor {
+            game_addr,
+            matchmaker_addr,
+            server_addr,
+            handle_signals,
+            shutting_down: false,
+        }
+    }
+
+    fn shutdown(&mut self, ctx: &mut Context<Self>) {
+        if self.shutting_down {
+            return;
+        }
+        self.shutting_down = true;
+
+        info!("Shutting down");
+
+        // Stop accepting new connections right away
+        self.server_addr.do_send(PauseServer);
+
+        ctx.run_later(SHUTDOWN_TIMEOUT, |_act, _ctx| {
+            warn!("Graceful shutdown timed out, exiting");
+            System::current().stop();
+        });
+
+        let server_addr = self.server_addr.clone();
+        Arbiter::spawn(
+            self.game_addr
+                .send(Shutdown)
+                .join(self.matchmaker_addr.send(Shutdown))
+                .then(move |_| server_addr.send(StopServer { graceful: true }))
+                .then(|_| {
+                    info!("Shutdown complete");
+                    System::current().stop();
+                    Ok(())
+                }),
+        );
+    }
+}
+
+impl Actor for SignalActor {
+    type Context = Context<SignalActor>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if !self.handle_signals {
+            return;
+        }
+
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Signal> for SignalActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) {
+        match msg.0 {
+            SignalType::Hup => {
+                info!("SIGHUP received, reloading config");
+                self.game_addr.do_send(ReloadConfig);
+            },
+            SignalType::Int | SignalType::Term | SignalType::Quit => {
+                self.shutdown(ctx);
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Handler<Shutdown> for SignalActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, ctx: &mut Self::Context) {
+        self.shutdown(ctx);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    actors::{game_actor::SpectatorEvent, GameActor, MatchmakerActor},
+    game::TICKS_PER_SECOND,
+    metrics::Metrics,
+    models::{
+        messages::{ClientStop, FindRoom},
+        spectator::{ServerToSpectator, SpectatorCommand, DEFAULT_ROOM},
+    },
+    AppState,
+};
+use actix::{
+    fut, Actor, ActorContext, ActorFuture, Addr, AsyncContext, Handler, StreamHandler, WrapFuture,
+};
+use actix_web::ws::{self, CloseReason};
+use serde::Serialize;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokyo::models::ServerToClient;
+
+// Spectators can't ask for fewer game states than this per second
+const MIN_UPDATE_RATE: f32 = 1.0;
+
+/// A browser watching a room. Unlike bots, spectators can pick which room
+/// they watch, follow a single player and throttle how often they get game
+/// states.
+#[derive(Debug)]
+pub struct SpectatorWsActor {
+    // The game of the main room
+    game_addr: Addr<GameActor>,
+    matchmaker_addr: Addr<MatchmakerActor>,
+    // The room being watched and its game
+    room: Option<(String, Addr<GameActor>)>,
+    // The room to watch once the actor has started
+    initial_room: Option<String>,
+    following: Option<u32>,
+    update_interval: Duration,
+    last_state_sent: Option<Instant>,
+    metrics: Arc<Metrics>,
+}
+
+impl SpectatorWsActor {
+    pub fn new(
+        game_addr: Addr<GameActor>,
+        matchmaker_addr: Addr<MatchmakerActor>,
+        room: String,
+        metrics: Arc<Metrics>,
+    ) -> SpectatorWsActor {
+        SpectatorWsActor {
+            game_addr,
+            matchmaker_addr,
+            room: None,
+            initial_room: Some(room),
+            following: None,
+            update_interval: Duration::from_secs(0),
+            last_state_sent: None,
+            metrics,
+        }
+    }
+
+    fn send_json<T: Serialize>(&self, msg: &T, ctx: &mut <Self as Actor>::Context) {
+        let start = Instant::now();
+        let text = serde_json::to_string(msg).unwrap();
+        self.metrics.record_serialization(start.elapsed());
+
+        ctx.text(text);
+        self.metrics.messages_sent_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn subscribe(&mut self, room: String, ctx: &mut <Self as Actor>::Context) {
+        if room == DEFAULT_ROOM {
+            let game_addr = self.game_addr.clone();
+            self.watch(room, game_addr, ctx);
+            return;
+        }
+
+        // Any other room is a matchmade match
+        let lookup = self.matchmaker_addr.send(FindRoom(room.clone())).into_actor(self).then(
+            move |result, act, ctx| {
+                match result {
+                    Ok(Some(game_addr)) => act.watch(room, game_addr, ctx),
+                    _ => act.send_json(
+                        &ServerToSpectator::Error(format!("Unknown room {}", room)),
+                        ctx,
+                    ),
+                }
+
+                fut::ok(())
+            },
+        );
+        ctx.spawn(lookup);
+    }
+
+    fn watch(
+        &mut self,
+        room: String,
+        game_addr: Addr<GameActor>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        self.unwatch(ctx);
+        game_addr.do_send(SpectatorEvent::Subscribe(ctx.address()));
+        self.room = Some((room.clone(), game_addr));
+        self.send_json(&ServerToSpectator::Subscribed(room), ctx);
+    }
+
+    fn unwatch(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if let Some((_, game_addr)) = self.room.take() {
+            game_addr.do_send(SpectatorEvent::Unsubscribe(ctx.address()));
+        }
+    }
+
+    fn handle_command(&mut self, cmd: SpectatorCommand, ctx: &mut <Self as Actor>::Context) {
+        match cmd {
+            SpectatorCommand::Subscribe(room) => {
+                self.subscribe(room, ctx);
+            },
+            SpectatorCommand::Follow(player_id) => {
+                self.following = player_id;
+                self.send_json(&ServerToSpectator::Following(player_id), ctx);
+            },
+            SpectatorCommand::Rate(rate) => {
+                let rate = rate.clamp(MIN_UPDATE_RATE, TICKS_PER_SECOND);
+                self.update_interval = Duration::from_secs_f32(1.0 / rate);
+                self.send_json(&ServerToSpectator::Rate(rate), ctx);
+            },
+        }
+    }
+}
+
+impl Actor for SpectatorWsActor {
+    type Context = ws::WebsocketContext<Self, AppState>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(room) = self.initial_room.take() {
+            self.subscribe(room, ctx);
+        }
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.unwatch(ctx);
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for SpectatorWsActor {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Text(text) => {
+                self.metrics.messages_received_total.fetch_add(1, Ordering::Relaxed);
+
+                match serde_json::from_str(&text) {
+                    Ok(cmd) => self.handle_command(cmd, ctx),
+                    Err(e) => {
+                        self.send_json(&ServerToSpectator::Error(e.to_string()), ctx);
+                    },
+                }
+            },
+            ws::Message::Ping(msg) => {
+                ctx.pong(&msg);
+            },
+            ws::Message::Close(_) => {
+                ctx.stop();
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Handler<ServerToClient> for SpectatorWsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServerToClient, ctx: &mut Self::Context) {
+        if let ServerToClient::GameState(_) = msg {
+            let now = Instant::now();
+            match self.last_state_sent {
+                Some(last) if now.duration_since(last) < self.update_interval => return,
+                _ => self.last_state_sent = Some(now),
+            }
+        }
+
+        self.send_json(&msg, ctx);
+    }
+}
+
+impl Handler<ServerToSpectator> for SpectatorWsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServerToSpectator, ctx: &mut Self::Context) {
+        match (msg, self.following) {
+            (ServerToSpectator::Events(events), Some(player_id)) => {
+                let events: Vec<_> =
+                    events.into_iter().filter(|event| event.involves(player_id)).collect();
+                if !events.is_empty() {
+                    self.send_json(&ServerToSpectator::Events(events), ctx);
+                }
+            },
+            (ServerToSpectator::Closed(room), _) => {
+                // The game is gone, so there's nothing to unsubscribe from
+                let watching = self.room.as_ref().is_some_and(|(name, _)| *name == room);
+                self.send_json(&ServerToSpectator::Closed(room), ctx);
+                if watching {
+                    self.room = None;
+                    self.subscribe(DEFAULT_ROOM.to_string(), ctx);
+                }
+            },
+            (msg, _) => self.send_json(&msg, ctx),
+        }
+    }
+}
+
+impl Handler<ClientStop> for SpectatorWsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientStop, ctx: &mut Self::Context) {
+        ctx.close(Some(CloseReason { code: msg.code, description: Some(msg.reason) }));
+    }
+}
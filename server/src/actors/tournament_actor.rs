@@ -0,0 +1,142 @@
+use crate::{
+    actors::GameActor,
+    models::messages::{
+        CancelTournament, GetMatchScores, GetTournament, ServerCommand, StartTournament,
+        TournamentUpdate,
+    },
+    tournament::{Tournament, TournamentPhase},
+};
+use actix::{
+    fut, Actor, ActorFuture, Addr, AsyncContext, Context, Handler, MessageResult, SpawnHandle,
+    WrapFuture,
+};
+use std::time::Duration;
+
+/// Runs the matches of a tournament one after the other, only letting the
+/// scheduled API keys into the arena while their match is on.
+#[derive(Debug)]
+pub struct TournamentActor {
+    game_addr: Addr<GameActor>,
+    tournament: Option<Tournament>,
+    match_duration: Duration,
+    intermission: Duration,
+    // The timer ending the current match or intermission
+    timer: Option<SpawnHandle>,
+}
+
+impl TournamentActor {
+    pub fn new(game_addr: Addr<GameActor>) -> TournamentActor {
+        TournamentActor {
+            game_addr,
+            tournament: None,
+            match_duration: Duration::from_secs(0),
+            intermission: Duration::from_secs(0),
+            timer: None,
+        }
+    }
+
+    fn broadcast_status(&self) {
+        let status = self.tournament.as_ref().map(|tournament| tournament.status());
+        self.game_addr.do_send(TournamentUpdate(status));
+    }
+
+    fn start_match(&mut self, ctx: &mut Context<Self>) {
+        let tournament = match self.tournament {
+            Some(ref mut tournament) => tournament,
+            None => return,
+        };
+
+        match tournament.current_match_keys() {
+            Some(keys) => {
+                info!("Starting tournament match between {:?}", keys);
+                tournament.phase = TournamentPhase::Playing;
+                self.game_addr.do_send(ServerCommand::StartMatch(keys));
+                self.timer =
+                    Some(ctx.run_later(self.match_duration, |act, ctx| act.finish_match(ctx)));
+            },
+            None => {
+                info!("Tournament finished");
+                self.game_addr.do_send(ServerCommand::EndMatch);
+                self.timer = None;
+            },
+        }
+
+        self.broadcast_status();
+    }
+
+    fn finish_match(&mut self, ctx: &mut Context<Self>) {
+        let scores =
+            self.game_addr.send(GetMatchScores).into_actor(self).then(|result, act, ctx| {
+                let scores = result.unwrap_or_else(|e| {
+                    error!("Failed to get the match scores: {}", e);
+                    Default::default()
+                });
+
+                if let Some(ref mut tournament) = act.tournament {
+                    tournament.record_result(&scores);
+
+                    if tournament.phase != TournamentPhase::Finished {
+                        tournament.phase = TournamentPhase::Intermission;
+                        act.timer =
+                            Some(ctx.run_later(act.intermission, |act, ctx| act.start_match(ctx)));
+                        act.broadcast_status();
+                    } else {
+                        act.start_match(ctx);
+                    }
+                }
+
+                fut::ok(())
+            });
+
+        self.timer = Some(ctx.spawn(scores));
+    }
+}
+
+impl Actor for TournamentActor {
+    type Context = Context<TournamentActor>;
+}
+
+impl Handler<StartTournament> for TournamentActor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: StartTournament, ctx: &mut Self::Context) -> Self::Result {
+        let running = self
+            .tournament
+            .as_ref()
+            .is_some_and(|tournament| tournament.phase != TournamentPhase::Finished);
+        if running {
+            return Err("A tournament is already running".to_string());
+        }
+
+        self.tournament = Some(msg.tournament);
+        self.match_duration = msg.match_duration;
+        self.intermission = msg.intermission;
+        self.start_match(ctx);
+
+        Ok(())
+    }
+}
+
+impl Handler<CancelTournament> for TournamentActor {
+    type Result = ();
+
+    fn handle(&mut self, _: CancelTournament, ctx: &mut Self::Context) {
+        if let Some(timer) = self.timer.take() {
+            ctx.cancel_future(timer);
+        }
+
+        if self.tournament.take().is_some() {
+            info!("Tournament cancelled");
+            self.game_addr.do_send(ServerCommand::EndMatch);
+            self.broadcast_status();
+        }
+    }
+}
+
+impl Handler<GetTournament> for TournamentActor {
+    type Result = MessageResult<GetTournament>;
+
+    fn handle(&mut self, _: GetTournament, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.tournament.as_ref().map(|tournament| tournament.status()))
+    }
+}
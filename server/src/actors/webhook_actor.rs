@@ -0,0 +1,412 @@
+use crate::config::SharedConfig;
+use actix::{fut, Actor, ActorFuture, AsyncContext, Context, Handler, Message, WrapFuture};
+use actix_web::client;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Deliveries waiting for a free connection. The oldest are dropped beyond
+// this, so a slow receiver costs events rather than memory.
+const MAX_QUEUED: usize = 256;
+
+const MAX_IN_FLIGHT: usize = 4;
+
+// Including the first try
+const MAX_ATTEMPTS: u32 = 4;
+
+// Doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPlayer {
+    pub id: u32,
+    pub team_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookResult {
+    pub team_name: String,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// The first kill of a match.
+    FirstBlood { killer: WebhookPlayer, victim: WebhookPlayer },
+    /// A player reached a milestone of kills without dying.
+    Streak { player: WebhookPlayer, kills: u32 },
+    /// Final scores of everyone who played, best first.
+    MatchEnd { results: Vec<WebhookResult> },
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::FirstBlood { .. } => "first_blood",
+            WebhookEvent::Streak { .. } => "streak",
+            WebhookEvent::MatchEnd { .. } => "match_end",
+        }
+    }
+}
+
+/// Sent by a game for every event worth telling the webhooks about.
+#[derive(Debug, Message)]
+pub struct Webhook {
+    pub room: String,
+    pub tick: u64,
+    pub event: WebhookEvent,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    time: f64,
+    room: &'a str,
+    tick: u64,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+#[derive(Debug)]
+struct Delivery {
+    url: String,
+    body: Arc<String>,
+    attempt: u32,
+}
+
+/// POSTs game events as JSON to the webhooks in the config. Games hand
+/// events over without waiting, and deliveries which fail are retried with
+/// a growing delay.
+#[derive(Debug)]
+pub struct WebhookActor {
+    config: SharedConfig,
+    queue: VecDeque<Delivery>,
+    in_flight: usize,
+    retry_delay: Duration,
+}
+
+impl WebhookActor {
+    pub fn new(config: SharedConfig) -> WebhookActor {
+        WebhookActor { config, queue: VecDeque::new(), in_flight: 0, retry_delay: RETRY_DELAY }
+    }
+
+    fn enqueue(&mut self, delivery: Delivery) {
+        if self.queue.len() == MAX_QUEUED {
+            if let Some(dropped) = self.queue.pop_front() {
+                warn!("Webhook queue is full, dropping an event for {}", dropped.url);
+            }
+        }
+        self.queue.push_back(delivery);
+    }
+
+    fn pump(&mut self, ctx: &mut Context<Self>) {
+        while self.in_flight < MAX_IN_FLIGHT {
+            match self.queue.pop_front() {
+                Some(delivery) => self.deliver(delivery, ctx),
+                None => break,
+            }
+        }
+    }
+
+    fn deliver(&mut self, delivery: Delivery, ctx: &mut Context<Self>) {
+        let request = client::post(&delivery.url)
+            .content_type("application/json")
+            .timeout(REQUEST_TIMEOUT)
+            .body(delivery.body.to_string());
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to build the webhook request for {}: {}", delivery.url, e);
+                return;
+            },
+        };
+
+        self.in_flight += 1;
+        let send = request.send().into_actor(self).then(move |result, act, ctx| {
+            act.in_flight -= 1;
+
+            let failure = match result {
+                Ok(ref response) if response.status().is_success() => None,
+                Ok(response) => Some(format!("got status {}", response.status())),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(failure) = failure {
+                act.retry(delivery, &failure, ctx);
+            }
+
+            act.pump(ctx);
+            fut::ok(())
+        });
+
+        ctx.spawn(send);
+    }
+
+    fn retry(&mut self, mut delivery: Delivery, failure: &str, ctx: &mut Context<Self>) {
+        delivery.attempt += 1;
+        if delivery.attempt >= MAX_ATTEMPTS {
+            error!(
+                "Giving up on webhook {} after {} attempts: {}",
+                delivery.url, MAX_ATTEMPTS, failure
+            );
+            return;
+        }
+
+        let delay = self.retry_delay * 2u32.pow(delivery.attempt - 1);
+        warn!("Webhook {} failed, retrying in {:?}: {}", delivery.url, delay, failure);
+        ctx.run_later(delay, move |act, ctx| {
+            act.enqueue(delivery);
+            act.pump(ctx);
+        });
+    }
+}
+
+impl Actor for WebhookActor {
+    type Context = Context<WebhookActor>;
+}
+
+impl Handler<Webhook> for WebhookActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Webhook, ctx: &mut Self::Context) {
+        let config = self.config.get();
+        let name = msg.event.name();
+        let targets: Vec<&String> = config
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.events.is_empty() || webhook.events.iter().any(|e| e == name))
+            .map(|webhook| &webhook.url)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs_f64())
+            .unwrap_or(0.0);
+        let payload = WebhookPayload { time, room: &msg.room, tick: msg.tick, event: &msg.event };
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => Arc::new(body),
+            Err(e) => {
+                error!("Failed to serialize webhook event: {}", e);
+                return;
+            },
+        };
+
+        for url in targets {
+            self.enqueue(Delivery { url: url.clone(), body: body.clone(), attempt: 0 });
+        }
+        self.pump(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, WebhookConfig};
+    use actix::{Addr, System};
+    use actix_web::{server, App, HttpRequest, HttpResponse};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Mutex,
+        },
+        thread,
+        time::Instant,
+    };
+    use tokyo::models::GameConfig;
+
+    // How long to wait for deliveries which should arrive
+    const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A local HTTP server standing in for the webhook receivers, with a
+    /// `WebhookActor` POSTing to it, both on an actor system of their own.
+    struct StandIn {
+        requests: Arc<Mutex<Vec<(String, String)>>>,
+        webhooks: Addr<WebhookActor>,
+        system: System,
+    }
+
+    impl StandIn {
+        /// Answers the first `failures` requests with a 500. `webhooks` are
+        /// the paths to POST to and the events each of them wants.
+        fn start(failures: usize, webhooks: &[(&str, &[&str])]) -> StandIn {
+            let requests = Arc::new(Mutex::new(vec![]));
+            let webhooks: Vec<(String, Vec<String>)> = webhooks
+                .iter()
+                .map(|(path, events)| {
+                    (path.to_string(), events.iter().map(|e| e.to_string()).collect())
+                })
+                .collect();
+
+            let (started_tx, started_rx) = mpsc::channel();
+            let server_requests = requests.clone();
+            thread::spawn(move || {
+                let system = System::new("webhook-test");
+
+                let failures = Arc::new(AtomicUsize::new(failures));
+                let receiver = server::new(move || {
+                    let requests = server_requests.clone();
+                    let failures = failures.clone();
+                    App::new().default_resource(move |r| {
+                        r.with(move |(req, body): (HttpRequest, String)| {
+                            requests.lock().unwrap().push((req.path().to_string(), body));
+
+                            let failing = failures
+                                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                                    left.checked_sub(1)
+                                })
+                                .is_ok();
+                            if failing {
+                                HttpResponse::InternalServerError().finish()
+                            } else {
+                                HttpResponse::Ok().finish()
+                            }
+                        })
+                    })
+                })
+                .bind("127.0.0.1:0")
+                .unwrap();
+                let url = format!("http://{}", receiver.addrs()[0]);
+                receiver.start();
+
+                let mut config = AppConfig::new(GameConfig { bound_x: 3500.0, bound_y: 2500.0 });
+                config.webhooks = webhooks
+                    .into_iter()
+                    .map(|(path, events)| WebhookConfig { url: format!("{}{}", url, path), events })
+                    .collect();
+                let mut actor = WebhookActor::new(SharedConfig::new(config, None));
+                actor.retry_delay = Duration::from_millis(10);
+
+                let _ = started_tx.send((actor.start(), System::current()));
+                let _ = system.run();
+            });
+
+            let (webhooks, system) = started_rx.recv().unwrap();
+            StandIn { requests, webhooks, system }
+        }
+
+        fn send(&self, event: WebhookEvent) {
+            self.webhooks.do_send(Webhook { room: "main".to_string(), tick: 1, event });
+        }
+
+        /// The requests received so far, once there are `count` of them or
+        /// the wait is over.
+        fn wait_for(&self, count: usize) -> Vec<(String, String)> {
+            let deadline = Instant::now() + DELIVERY_TIMEOUT;
+            while self.requests.lock().unwrap().len() < count && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            self.system.stop();
+        }
+    }
+
+    fn match_end() -> WebhookEvent {
+        WebhookEvent::MatchEnd {
+            results: vec![WebhookResult { team_name: "ferris".to_string(), score: 10 }],
+        }
+    }
+
+    fn first_blood() -> WebhookEvent {
+        let player = |id| WebhookPlayer { id, team_name: None };
+        WebhookEvent::FirstBlood { killer: player(1), victim: player(2) }
+    }
+
+    #[test]
+    fn delivers_events() {
+        let stand_in = StandIn::start(0, &[("/hook", &[])]);
+        stand_in.send(match_end());
+
+        let requests = stand_in.wait_for(1);
+        assert_eq!(requests.len(), 1);
+        let (path, body) = &requests[0];
+        assert_eq!(path, "/hook");
+
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["event"], "match_end");
+        assert_eq!(payload["room"], "main");
+        assert_eq!(payload["tick"], 1);
+        assert_eq!(payload["results"][0]["team_name"], "ferris");
+    }
+
+    #[test]
+    fn retries_after_a_server_error() {
+        let stand_in = StandIn::start(1, &[("/hook", &[])]);
+        stand_in.send(match_end());
+
+        let requests = stand_in.wait_for(2);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], requests[1]);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let stand_in = StandIn::start(usize::MAX, &[("/hook", &[])]);
+        stand_in.send(match_end());
+
+        stand_in.wait_for(MAX_ATTEMPTS as usize);
+        // Long enough for another retry to show up
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(stand_in.requests.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn filters_events() {
+        let stand_in = StandIn::start(0, &[("/all", &[]), ("/match_end", &["match_end"])]);
+        stand_in.send(first_blood());
+        stand_in.send(match_end());
+
+        stand_in.wait_for(3);
+        // Long enough for a delivery which should have been filtered out
+        thread::sleep(Duration::from_millis(200));
+
+        let mut received: Vec<(String, String)> = stand_in
+            .requests
+            .lock()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .map(|(path, body)| {
+                let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+                (path, payload["event"].as_str().unwrap().to_string())
+            })
+            .collect();
+        received.sort();
+
+        let expected = vec![
+            ("/all".to_string(), "first_blood".to_string()),
+            ("/all".to_string(), "match_end".to_string()),
+            ("/match_end".to_string(), "match_end".to_string()),
+        ];
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn drops_the_oldest_delivery_when_full() {
+        let config = AppConfig::new(GameConfig { bound_x: 3500.0, bound_y: 2500.0 });
+        let mut actor = WebhookActor::new(SharedConfig::new(config, None));
+
+        for i in 0..=MAX_QUEUED {
+            actor.enqueue(Delivery {
+                url: format!("http://localhost/{}", i),
+                body: Arc::new(String::new()),
+                attempt: 0,
+            });
+        }
+
+        assert_eq!(actor.queue.len(), MAX_QUEUED);
+        assert_eq!(actor.queue.front().unwrap().url, "http://localhost/1");
+        assert_eq!(actor.queue.back().unwrap().url, format!("http://localhost/{}", MAX_QUEUED));
+    }
+}
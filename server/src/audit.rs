@@ -0,0 +1,126 @@
+use crate::models::messages::ServerCommand;
+use failure::{format_err, Error};
+use std::{
+    fs::{File, OpenOptions},
+    io::{LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokyo::models::ItemType;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Connect {
+        player_id: u32,
+        team_name: String,
+        ip: Option<String>,
+    },
+    Disconnect {
+        player_id: Option<u32>,
+        ip: Option<String>,
+    },
+    Rejected {
+        reason: String,
+        ip: Option<String>,
+    },
+    Kick {
+        reason: String,
+    },
+    Kill {
+        killer_id: u32,
+        victim_id: u32,
+        victim_api_key: Option<String>,
+        bullet_id: Option<u32>,
+        killer_position: Option<(f32, f32)>,
+        victim_position: (f32, f32),
+    },
+    ItemPickup {
+        player_id: u32,
+        item_id: u32,
+        item_type: ItemType,
+    },
+    Admin {
+        command: ServerCommand,
+    },
+    ConfigReloaded {
+        success: bool,
+    },
+    Shutdown,
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room: Option<&'a str>,
+    tick: u64,
+    api_key: Option<&'a str>,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// An append-only JSON lines log of everything that could matter when
+/// reconstructing a match. Records are dropped silently when no path is
+/// configured. Clones append to the same file.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    writer: Option<Arc<Mutex<LineWriter<File>>>>,
+    // Set for the games of matchmade rooms, whose player IDs and ticks are
+    // only unique within the room
+    room: Option<String>,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<&Path>) -> Result<AuditLog, Error> {
+        let writer = match path {
+            Some(path) => {
+                let file =
+                    OpenOptions::new().create(true).append(true).open(path).map_err(|e| {
+                        format_err!("Failed to open audit log {}: {}", path.display(), e)
+                    })?;
+                Some(Arc::new(Mutex::new(LineWriter::new(file))))
+            },
+            None => None,
+        };
+
+        Ok(AuditLog { writer, room: None })
+    }
+
+    /// A handle to the same log which tags every record with `room`.
+    pub fn for_room(&self, room: &str) -> AuditLog {
+        AuditLog { writer: self.writer.clone(), room: Some(room.to_string()) }
+    }
+
+    pub fn record(&mut self, tick: u64, api_key: Option<&str>, event: AuditEvent) {
+        let mut writer = match self.writer {
+            Some(ref writer) => writer.lock().expect("audit log lock should never be poisoned"),
+            None => return,
+        };
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs_f64())
+            .unwrap_or(0.0);
+        let room = self.room.as_deref();
+        let record = AuditRecord { time, room, tick, api_key, event: &event };
+
+        let result = serde_json::to_writer(&mut *writer, &record)
+            .map_err(Error::from)
+            .and_then(|_| writer.write_all(b"\n").map_err(Error::from));
+
+        if let Err(e) = result {
+            error!("Failed to write audit log record: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(ref writer) = self.writer {
+            let mut writer = writer.lock().expect("audit log lock should never be poisoned");
+            if let Err(e) = writer.flush() {
+                error!("Failed to flush audit log: {}", e);
+            }
+        }
+    }
+}
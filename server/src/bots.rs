@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+use tokyo::{
+    analyzer::Analyzer,
+    behavior::{Behavior, Chase, Dodge, FireAt, Random, Sequence, Target},
+    models::{ClientState, GameCommand, MIN_COMMAND_INTERVAL},
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    /// Wanders around and takes the odd shot at whoever is closest.
+    Easy,
+    /// Chases down whoever is closest and fires at them.
+    #[default]
+    Normal,
+    /// Goes after the leader, and dodges bullets on the way.
+    Hard,
+}
+
+impl Difficulty {
+    fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    // Slower bots react later and fire less often
+    fn think_interval(self) -> Duration {
+        match self {
+            Difficulty::Easy => Duration::from_millis(400),
+            Difficulty::Normal => Duration::from_millis(150),
+            Difficulty::Hard => MIN_COMMAND_INTERVAL,
+        }
+    }
+
+    fn next_behavior(self) -> Box<dyn Behavior> {
+        match self {
+            Difficulty::Easy => {
+                Box::new(Sequence::with_slice(&[&Random, &Random, &FireAt::new(Target::Closest)]))
+            },
+            Difficulty::Normal => Box::new(Sequence::with_slice(&[
+                &Chase::new(Target::Closest, 400.0),
+                &FireAt::with_times(Target::Closest, 2),
+            ])),
+            Difficulty::Hard => Box::new(Sequence::with_slice(&[
+                &FireAt::new(Target::HighestScore),
+                &Chase::new(Target::HighestScore, 300.0),
+                &FireAt::with_times(Target::HighestScore, 2),
+            ])),
+        }
+    }
+}
+
+/// A bot played by the server itself, using the same `Analyzer` and
+/// behaviors as the example clients. The game actor feeds it every game state
+/// and sends its commands to the game loop like any other player's.
+#[derive(Debug)]
+pub struct ServerBot {
+    pub player_id: u32,
+    difficulty: Difficulty,
+    analyzer: Analyzer,
+    behavior: Box<dyn Behavior>,
+    // Only hard bots dodge
+    dodge: Option<Dodge>,
+    next_command_at: Instant,
+}
+
+impl ServerBot {
+    pub fn new(player_id: u32, difficulty: Difficulty) -> ServerBot {
+        ServerBot {
+            player_id,
+            difficulty,
+            analyzer: Analyzer::default(),
+            behavior: Box::new(Sequence::new()),
+            dodge: if difficulty == Difficulty::Hard { Some(Dodge::new(200.0, 1.0)) } else { None },
+            next_command_at: Instant::now(),
+        }
+    }
+
+    pub fn team_name(&self) -> String {
+        format!("Bot {} ({})", self.player_id, self.difficulty.name())
+    }
+
+    /// Returns the bot's next command, if it's alive and ready to make one.
+    /// `state.id` must be the bot's player ID.
+    pub fn tick(&mut self, state: &ClientState, now: Instant) -> Option<GameCommand> {
+        let alive = state.game_state.players.iter().any(|player| player.id == self.player_id);
+        if !alive || now < self.next_command_at {
+            return None;
+        }
+        self.next_command_at = now + self.difficulty.think_interval();
+
+        self.analyzer.push_state(state, now);
+
+        if let Some(ref mut dodge) = self.dodge {
+            if let Some(command) = dodge.next_command(&self.analyzer) {
+                return Some(command);
+            }
+        }
+
+        if let Some(command) = self.behavior.next_command(&self.analyzer) {
+            return Some(command);
+        }
+
+        self.behavior = self.difficulty.next_behavior();
+        self.behavior.next_command(&self.analyzer)
+    }
+}
@@ -0,0 +1,406 @@
+use crate::bots::Difficulty;
+use failure::{format_err, Error};
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokyo::models::GameConfig;
+
+pub const DEFAULT_CONFIG_FILE_PATH: &str = "tokyo.toml";
+
+#[derive(Deserialize, Clone)]
+pub struct AppConfig {
+    pub server_port: Option<u16>,
+    /// Serves HTTPS and WSS on a second port, next to the plain one.
+    pub tls: Option<TlsConfig>,
+    pub api_keys: HashSet<String>,
+    pub dev_mode: bool,
+    pub admin_token: Option<String>,
+    pub registry_path: Option<PathBuf>,
+    /// Where to keep the rating of every API key across restarts.
+    pub ratings_path: Option<PathBuf>,
+    /// Where to append the JSON lines audit log, if anywhere.
+    pub audit_log_path: Option<PathBuf>,
+    /// How long a disconnected bot keeps its player ID and score.
+    #[serde(default)]
+    pub reconnect_grace_secs: u64,
+    /// Where to record a replay of every match, if anywhere.
+    pub replay_dir: Option<PathBuf>,
+    /// How many replays to keep before deleting the oldest ones.
+    #[serde(default = "default_max_replays")]
+    pub max_replays: usize,
+    pub game_config: GameConfig,
+    /// Matches for bots which connect with `?queue=ranked`.
+    #[serde(default)]
+    pub matchmaking: MatchmakingConfig,
+    /// Bots played by the server, so there's something to shoot at.
+    #[serde(default)]
+    pub server_bots: ServerBotsConfig,
+    /// Connection caps, command budgets and penalties for going over them.
+    #[serde(default)]
+    pub abuse: AbuseConfig,
+    /// Where to POST match results and notable kills.
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+fn default_max_replays() -> usize {
+    50
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub port: u16,
+    /// PEM file with the certificate, followed by any intermediates.
+    pub cert_path: PathBuf,
+    /// PEM file with the private key of the certificate.
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn acceptor(&self) -> Result<SslAcceptorBuilder, Error> {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        builder.set_private_key_file(&self.key_path, SslFiletype::PEM).map_err(|e| {
+            format_err!("Failed to load TLS key {}: {}", self.key_path.display(), e)
+        })?;
+        builder.set_certificate_chain_file(&self.cert_path).map_err(|e| {
+            format_err!("Failed to load TLS certificate {}: {}", self.cert_path.display(), e)
+        })?;
+        builder.check_private_key()?;
+
+        Ok(builder)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MatchmakingConfig {
+    /// How many bots play in a match.
+    pub match_size: usize,
+    pub match_secs: u64,
+    /// The widest rating spread between the bots of a match.
+    pub max_rating_gap: f64,
+    /// After waiting this long, a bot is matched with the closest rated bots
+    /// in the queue, however far apart they are and even if there aren't
+    /// enough for a full match.
+    pub max_wait_secs: u64,
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> MatchmakingConfig {
+        MatchmakingConfig {
+            match_size: 4,
+            match_secs: 180,
+            max_rating_gap: 200.0,
+            max_wait_secs: 60,
+        }
+    }
+}
+
+impl MatchmakingConfig {
+    pub fn match_duration(&self) -> Duration {
+        Duration::from_secs(self.match_secs)
+    }
+
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_secs(self.max_wait_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct ServerBotsConfig {
+    /// The main game is topped up with server bots until this many ships
+    /// are playing. They leave one by one as other bots join.
+    pub count: usize,
+    pub difficulty: Difficulty,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AbuseConfig {
+    /// Bot sockets open at once from a single IP, 0 for no limit.
+    pub max_connections_per_ip: usize,
+    /// Takes the IP from the `Forwarded` or `X-Forwarded-For` header instead
+    /// of the socket. Only for a reverse proxy which sets that header, as
+    /// bots can send it themselves.
+    pub trust_forwarded_for: bool,
+    /// Sockets open at once per API key, 0 for no limit. A reconnecting bot
+    /// briefly has two, until the game closes the old one.
+    pub max_connections_per_key: usize,
+    pub commands_per_sec: u32,
+    /// Command budgets for particular API keys, overriding `commands_per_sec`.
+    pub key_commands_per_sec: HashMap<String, u32>,
+    /// Rate limited commands within `strike_window_secs` which earn a strike.
+    /// The first strike is a warning, the second a mute and the third a kick
+    /// and a timed ban.
+    pub violations_per_strike: u32,
+    pub strike_window_secs: u64,
+    /// Strikes are forgotten after this long without a new one.
+    pub strike_decay_secs: u64,
+    pub mute_secs: u64,
+    pub ban_secs: u64,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> AbuseConfig {
+        AbuseConfig {
+            max_connections_per_ip: 8,
+            trust_forwarded_for: false,
+            max_connections_per_key: 2,
+            commands_per_sec: 22,
+            key_commands_per_sec: HashMap::new(),
+            violations_per_strike: 20,
+            strike_window_secs: 10,
+            strike_decay_secs: 300,
+            mute_secs: 10,
+            ban_secs: 300,
+        }
+    }
+}
+
+impl AbuseConfig {
+    pub fn commands_per_sec(&self, api_key: &str) -> u32 {
+        self.key_commands_per_sec.get(api_key).cloned().unwrap_or(self.commands_per_sec)
+    }
+
+    pub fn strike_window(&self) -> Duration {
+        Duration::from_secs(self.strike_window_secs)
+    }
+
+    pub fn strike_decay(&self) -> Duration {
+        Duration::from_secs(self.strike_decay_secs)
+    }
+
+    pub fn mute_duration(&self) -> Duration {
+        Duration::from_secs(self.mute_secs)
+    }
+
+    pub fn ban_duration(&self) -> Duration {
+        Duration::from_secs(self.ban_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// The events to send, out of `first_blood`, `streak` and `match_end`.
+    /// Every event is sent if empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+// Printed on startup, so the admin token is left out
+impl fmt::Debug for AppConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AppConfig")
+            .field("server_port", &self.server_port)
+            .field("tls", &self.tls)
+            .field("api_keys", &self.api_keys)
+            .field("dev_mode", &self.dev_mode)
+            .field("admin_token", &self.admin_token.as_ref().map(|_| "<redacted>"))
+            .field("registry_path", &self.registry_path)
+            .field("ratings_path", &self.ratings_path)
+            .field("audit_log_path", &self.audit_log_path)
+            .field("reconnect_grace_secs", &self.reconnect_grace_secs)
+            .field("replay_dir", &self.replay_dir)
+            .field("max_replays", &self.max_replays)
+            .field("game_config", &self.game_config)
+            .field("matchmaking", &self.matchmaking)
+            .field("server_bots", &self.server_bots)
+            .field("abuse", &self.abuse)
+            .field("webhooks", &self.webhooks)
+            .finish()
+    }
+}
+
+impl AppConfig {
+    /// A config with every optional feature turned off, for servers which are
+    /// started from code rather than a config file. Only `dev_mode` or
+    /// `api_keys` need setting before bots can connect.
+    pub fn new(game_config: GameConfig) -> AppConfig {
+        AppConfig {
+            server_port: None,
+            tls: None,
+            api_keys: HashSet::new(),
+            dev_mode: false,
+            admin_token: None,
+            registry_path: None,
+            ratings_path: None,
+            audit_log_path: None,
+            reconnect_grace_secs: 0,
+            replay_dir: None,
+            max_replays: default_max_replays(),
+            game_config,
+            matchmaking: MatchmakingConfig::default(),
+            server_bots: ServerBotsConfig::default(),
+            abuse: AbuseConfig::default(),
+            webhooks: vec![],
+        }
+    }
+
+    /// Reads the config file at `path`, then lets environment variables
+    /// override the values from the file.
+    pub fn load(path: &Path) -> Result<AppConfig, Error> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format_err!("Failed to read config file {}: {}", path.display(), e))?;
+        let mut config: AppConfig = toml::from_slice(&bytes)
+            .map_err(|e| format_err!("Failed to parse config file {}: {}", path.display(), e))?;
+
+        config.apply_env_overrides()?;
+
+        Ok(config)
+    }
+
+    pub fn reconnect_grace_period(&self) -> Duration {
+        Duration::from_secs(self.reconnect_grace_secs)
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), Error> {
+        if let Ok(port) = env::var("SERVER_PORT") {
+            self.server_port =
+                Some(port.parse().map_err(|_| format_err!("Invalid SERVER_PORT: {}", port))?);
+        }
+
+        if let Ok(keys) = env::var("API_KEYS") {
+            self.api_keys = keys
+                .split(',')
+                .map(|key| key.trim())
+                .filter(|key| !key.is_empty())
+                .map(|key| key.to_string())
+                .collect();
+        }
+
+        if let Ok(dev_mode) = env::var("DEV_MODE") {
+            self.dev_mode =
+                dev_mode.parse().map_err(|_| format_err!("Invalid DEV_MODE: {}", dev_mode))?;
+        }
+
+        if let Ok(admin_token) = env::var("ADMIN_TOKEN") {
+            self.admin_token = Some(admin_token);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the config file path given with `--config <path>`, or the default.
+pub fn config_path_from_args() -> PathBuf {
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" || arg == "-c" {
+            if let Some(path) = args.next() {
+                return PathBuf::from(path);
+            }
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return PathBuf::from(path);
+        }
+    }
+
+    PathBuf::from(DEFAULT_CONFIG_FILE_PATH)
+}
+
+/// The names of the settings which differ between two configs, so a reload
+/// can be logged without printing the admin token.
+fn changed_fields(previous: &AppConfig, config: &AppConfig) -> Vec<&'static str> {
+    macro_rules! changed {
+        ($($field:ident),*) => {
+            vec![$(
+                (stringify!($field), previous.$field != config.$field)
+            ),*]
+        };
+    }
+
+    changed!(
+        server_port,
+        tls,
+        api_keys,
+        dev_mode,
+        admin_token,
+        registry_path,
+        ratings_path,
+        audit_log_path,
+        reconnect_grace_secs,
+        replay_dir,
+        max_replays,
+        game_config,
+        matchmaking,
+        server_bots,
+        abuse,
+        webhooks
+    )
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// A handle to the current `AppConfig` which can be cheaply cloned and shared
+/// between threads. Reloading swaps in a new config for every handle, while
+/// readers holding an older `Arc<AppConfig>` keep a consistent snapshot.
+#[derive(Debug, Clone)]
+pub struct SharedConfig {
+    /// The file the config is reloaded from, if it came from one.
+    path: Option<PathBuf>,
+    current: Arc<RwLock<Arc<AppConfig>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: AppConfig, path: Option<PathBuf>) -> SharedConfig {
+        SharedConfig { path, current: Arc::new(RwLock::new(Arc::new(config))) }
+    }
+
+    pub fn load(path: PathBuf) -> Result<SharedConfig, Error> {
+        let config = AppConfig::load(&path)?;
+
+        Ok(SharedConfig::new(config, Some(path)))
+    }
+
+    pub fn get(&self) -> Arc<AppConfig> {
+        self.current.read().expect("config lock should never be poisoned").clone()
+    }
+
+    /// Re-reads the config file and environment. On failure, or if there is
+    /// no config file, the previous config stays in place.
+    pub fn reload(&self) -> Result<Arc<AppConfig>, Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Err(format_err!("The config wasn't loaded from a file")),
+        };
+        let config = Arc::new(AppConfig::load(path)?);
+        let previous = std::mem::replace(
+            &mut *self.current.write().expect("config lock should never be poisoned"),
+            config.clone(),
+        );
+
+        if previous.server_port != config.server_port {
+            warn!("server_port changed in config, restart the server to apply it");
+        }
+        if previous.tls != config.tls {
+            warn!("tls changed in config, restart the server to apply it");
+        }
+        if previous.registry_path != config.registry_path {
+            warn!("registry_path changed in config, restart the server to apply it");
+        }
+        if previous.ratings_path != config.ratings_path {
+            warn!("ratings_path changed in config, restart the server to apply it");
+        }
+        if previous.audit_log_path != config.audit_log_path {
+            warn!("audit_log_path changed in config, restart the server to apply it");
+        }
+
+        let changed = changed_fields(&previous, &config);
+        if changed.is_empty() {
+            info!("Config reloaded from {}, nothing changed", path.display());
+        } else {
+            info!("Config reloaded from {}, changed {}", path.display(), changed.join(", "));
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    game::{MAX_TIME_SCALE, MIN_TIME_SCALE},
+    models::messages::{
+        CancelTournament, GetGameConfig, ListConnections, ReloadConfig, ServerCommand,
+        StartTournament,
+    },
+    registry::{DisplayNameTaken, TeamEntry},
+    tournament::{Entrant, Tournament, TournamentFormat},
+    AppState,
+};
+use actix_web::{
+    http::header, AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Path, State,
+};
+use bytes::Bytes;
+use futures::{future, Future};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tokyo::models::GameConfig;
+
+#[derive(Debug, Deserialize)]
+pub struct TournamentSpec {
+    /// Registered API keys, best seed first.
+    keys: Vec<String>,
+    format: TournamentFormat,
+    #[serde(default = "default_match_secs")]
+    match_secs: u64,
+    #[serde(default = "default_intermission_secs")]
+    intermission_secs: u64,
+}
+
+fn default_match_secs() -> u64 {
+    180
+}
+
+fn default_intermission_secs() -> u64 {
+    30
+}
+
+/// Checks the `Authorization: Bearer <token>` header against the configured
+/// admin token. The admin API is disabled entirely if no token is configured.
+pub fn authorize(req: &HttpRequest<AppState>) -> Result<(), actix_web::Error> {
+    let config = req.state().config.get();
+    let admin_token = match config.admin_token {
+        Some(ref token) => token,
+        None => return Err(actix_web::error::ErrorForbidden("Admin API is disabled")),
+    };
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.starts_with("Bearer "))
+        .map(|value| value["Bearer ".len()..].trim());
+
+    // Compared in constant time, so the token can't be guessed byte by byte
+    match provided {
+        Some(token)
+            if token.len() == admin_token.len()
+                && openssl::memcmp::eq(token.as_bytes(), admin_token.as_bytes()) =>
+        {
+            Ok(())
+        },
+        _ => Err(actix_web::error::ErrorUnauthorized("Invalid admin token")),
+    }
+}
+
+/// Parses a JSON request body. Admin handlers take the raw body and call
+/// this after `authorize`, so callers without the token can't probe the
+/// validation.
+fn parse_body<T: DeserializeOwned>(body: &Bytes) -> Result<T, actix_web::Error> {
+    serde_json::from_slice(body)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid JSON: {}", e)))
+}
+
+fn send_command(
+    req: &HttpRequest<AppState>,
+    state: &State<AppState>,
+    cmd: ServerCommand,
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(req)?;
+    state.game_addr.do_send(cmd);
+    Ok(HttpResponse::Ok().body("done"))
+}
+
+pub fn connections_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::err(e));
+    }
+
+    state
+        .game_addr
+        .send(ListConnections)
+        .from_err()
+        .map(|connections| HttpResponse::Ok().json(connections))
+        .responder()
+}
+
+pub fn kick_handler(
+    (req, state, api_key): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Kick(api_key.into_inner()))
+}
+
+pub fn ban_handler(
+    (req, state, api_key): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Ban(api_key.into_inner()))
+}
+
+/// Lifts both a ban from the admin API and a timed ban for going over the
+/// command budget, along with any strikes.
+pub fn unban_handler(
+    (req, state, api_key): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Unban(api_key.to_string()))?;
+    state.guard.pardon(&api_key);
+    Ok(HttpResponse::Ok().body("done"))
+}
+
+pub fn abuse_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+    Ok(HttpResponse::Ok().json(state.guard.status()))
+}
+
+pub fn pause_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Pause)
+}
+
+pub fn resume_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Resume)
+}
+
+pub fn step_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Step)
+}
+
+pub fn time_scale_handler(
+    (req, state, scale): (HttpRequest<AppState>, State<AppState>, Path<f32>),
+) -> Result<HttpResponse, actix_web::Error> {
+    let scale = scale.into_inner();
+    if !(MIN_TIME_SCALE..=MAX_TIME_SCALE).contains(&scale) {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Time scale must be between {} and {}",
+            MIN_TIME_SCALE, MAX_TIME_SCALE
+        )));
+    }
+
+    send_command(&req, &state, ServerCommand::SetTimeScale(scale))
+}
+
+pub fn reset_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    send_command(&req, &state, ServerCommand::Reset)
+}
+
+pub fn get_config_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::err(e));
+    }
+
+    state
+        .game_addr
+        .send(GetGameConfig)
+        .from_err()
+        .map(|config| HttpResponse::Ok().json(config))
+        .responder()
+}
+
+pub fn update_config_handler(
+    (req, state, body): (HttpRequest<AppState>, State<AppState>, Bytes),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+
+    let config: GameConfig = parse_body(&body)?;
+    if config.bound_x <= 0.0 || config.bound_y <= 0.0 {
+        return Err(actix_web::error::ErrorBadRequest("Bounds must be positive"));
+    }
+
+    send_command(&req, &state, ServerCommand::UpdateGameConfig(config))
+}
+
+pub fn reload_config_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::err(e));
+    }
+
+    state
+        .game_addr
+        .send(ReloadConfig)
+        .from_err()
+        .map(|result| match result {
+            Ok(()) => HttpResponse::Ok().body("done"),
+            Err(e) => HttpResponse::InternalServerError().body(e),
+        })
+        .responder()
+}
+
+pub fn teams_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+    Ok(HttpResponse::Ok().json(state.registry.list()))
+}
+
+pub fn put_team_handler(
+    (req, state, api_key, body): (HttpRequest<AppState>, State<AppState>, Path<String>, Bytes),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+
+    let api_key = api_key.into_inner();
+    let entry: TeamEntry = parse_body(&body)?;
+
+    state.registry.upsert(api_key.clone(), entry.clone()).map_err(|e| {
+        match e.downcast::<DisplayNameTaken>() {
+            Ok(taken) => actix_web::error::ErrorBadRequest(taken.to_string()),
+            Err(e) => actix_web::error::ErrorInternalServerError(e),
+        }
+    })?;
+
+    if entry.enabled {
+        state.game_addr.do_send(ServerCommand::UpdateTeam(
+            api_key,
+            entry.display_name,
+            entry.color,
+        ));
+    } else {
+        state.game_addr.do_send(ServerCommand::Kick(api_key));
+    }
+
+    Ok(HttpResponse::Ok().body("done"))
+}
+
+pub fn delete_team_handler(
+    (req, state, api_key): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+
+    if state
+        .registry
+        .remove(&api_key)
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .is_none()
+    {
+        return Err(actix_web::error::ErrorNotFound("No such API key"));
+    }
+
+    // Bots whose key is still valid without the registry can stay
+    let config = state.config.get();
+    if !config.dev_mode && !config.api_keys.contains(api_key.as_str()) {
+        state.game_addr.do_send(ServerCommand::Kick(api_key.into_inner()));
+    }
+
+    Ok(HttpResponse::Ok().body("done"))
+}
+
+pub fn start_tournament_handler(
+    (req, state, body): (HttpRequest<AppState>, State<AppState>, Bytes),
+) -> FutureResponse<HttpResponse> {
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::err(e));
+    }
+
+    let spec: TournamentSpec = match parse_body(&body) {
+        Ok(spec) => spec,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let mut entrants = vec![];
+    for api_key in spec.keys {
+        match state.registry.get(&api_key) {
+            Some(ref entry) if entry.enabled => {
+                entrants.push(Entrant { api_key, name: entry.display_name.clone() })
+            },
+            _ => {
+                return Box::new(future::err(actix_web::error::ErrorBadRequest(format!(
+                    "{} is not a registered and enabled API key",
+                    api_key
+                ))));
+            },
+        }
+    }
+
+    let tournament = match Tournament::new(spec.format, entrants) {
+        Ok(tournament) => tournament,
+        Err(e) => return Box::new(future::err(actix_web::error::ErrorBadRequest(e))),
+    };
+
+    state
+        .tournament_addr
+        .send(StartTournament {
+            tournament,
+            match_duration: Duration::from_secs(spec.match_secs),
+            intermission: Duration::from_secs(spec.intermission_secs),
+        })
+        .from_err()
+        .map(|result| match result {
+            Ok(()) => HttpResponse::Ok().body("done"),
+            Err(e) => HttpResponse::Conflict().body(e),
+        })
+        .responder()
+}
+
+pub fn cancel_tournament_handler(
+    (req, state): (HttpRequest<AppState>, State<AppState>),
+) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req)?;
+    state.tournament_addr.do_send(CancelTournament);
+    Ok(HttpResponse::Ok().body("done"))
+}
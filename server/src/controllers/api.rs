@@ -1,45 +1,319 @@
 use crate::{
-    actors::ClientWsActor, AppState,
-    models::messages::ServerCommand,
+    abuse::Rejection,
+    actors::{
+        matchmaker_actor::RANKED_QUEUE, BotConnection, ClientWsActor, Lobby, ReplayWsActor,
+        SpectatorWsActor,
+    },
+    models::{
+        messages::{
+            ApiFeed, ApiSubscribe, GameStatus, GetMatchmaking, GetScoreboard, GetSnapshot,
+            GetStatus, GetTournament, ListConnections,
+        },
+        spectator::DEFAULT_ROOM,
+    },
+    replay::{self, Replay},
+    AppState,
 };
-use actix_web::{HttpRequest, Query, State, http::StatusCode};
+use actix_web::{
+    error::InternalError,
+    fs::NamedFile,
+    http::{header, StatusCode},
+    AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Path, Query, State,
+};
+use bytes::Bytes;
+use futures::{sync::mpsc, Future, Stream};
+use std::time::Duration;
+
+// /healthz fails once the game loop has gone this long without a tick
+const MAX_TICK_AGE: Duration = Duration::from_secs(2);
+
+// The read-only API is meant for dashboards on other origins
+const ALLOW_ANY_ORIGIN: (&str, &str) = ("Access-Control-Allow-Origin", "*");
+
+// Snapshots buffered for a slow event stream client before they're skipped
+const EVENT_STREAM_BUFFER: usize = 4;
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
 
 #[derive(Debug, Deserialize)]
 pub struct QueryString {
     key: String,
-    name: String,
+    name: Option<String>,
+    /// Set to `ranked` to wait for a matchmade match instead of joining the
+    /// main game.
+    queue: Option<String>,
+    /// Bots can only pick the main room, the others are matchmade.
+    room: Option<String>,
 }
 
 pub fn socket_handler(
     (req, state, query): (HttpRequest<AppState>, State<AppState>, Query<QueryString>),
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    if crate::APP_CONFIG.dev_mode || crate::APP_CONFIG.api_keys.contains(&query.key) {
-        actix_web::ws::start(
-            &req,
-            ClientWsActor::new(state.game_addr.clone(), query.key.clone(), query.name.clone()),
-        )
-    } else {
-        Err(actix_web::error::ErrorBadRequest("Invalid API Key"))
+    let config = state.config.get();
+
+    // Registered keys always get their locked display name, other keys are
+    // only accepted from the config file or in dev mode, and can't pass for
+    // a registered team.
+    let (team_name, color) = match state.registry.get(&query.key) {
+        Some(ref entry) if !entry.enabled => {
+            return Err(actix_web::error::ErrorForbidden("This API key has been disabled"));
+        },
+        Some(entry) => (entry.display_name, entry.color),
+        None if config.dev_mode || config.api_keys.contains(&query.key) => {
+            let name = query.name.clone().unwrap_or_else(|| query.key.clone());
+            if state.registry.is_display_name(&name) {
+                return Err(actix_web::error::ErrorBadRequest(format!(
+                    "The name {} belongs to a registered team",
+                    name
+                )));
+            }
+            (name, None)
+        },
+        None => return Err(actix_web::error::ErrorBadRequest("Invalid API Key")),
+    };
+
+    if let Some(ref room) = query.room {
+        if room != DEFAULT_ROOM {
+            return Err(actix_web::error::ErrorBadRequest(format!("Unknown room {}", room)));
+        }
+    }
+
+    let lobby = match query.queue.as_deref() {
+        None => Lobby::Game(state.game_addr.clone()),
+        Some(RANKED_QUEUE) => Lobby::Queue(state.matchmaker_addr.clone()),
+        Some(queue) => {
+            return Err(actix_web::error::ErrorBadRequest(format!("Unknown queue {}", queue)));
+        },
+    };
+
+    let ip = client_ip(&req, config.abuse.trust_forwarded_for);
+
+    let permit = state.guard.connect(&query.key, ip.as_deref()).map_err(rejection_error)?;
+
+    let connection = BotConnection {
+        api_key: query.key.clone(),
+        team_name,
+        color,
+        ip,
+        guard: state.guard.clone(),
+        permit,
+    };
+
+    actix_web::ws::start(&req, ClientWsActor::new(lobby, connection, state.metrics.clone()))
+}
+
+/// The IP a bot connects from, without the port. Forwarded headers are
+/// only used when a trusted proxy sets them.
+fn client_ip(req: &HttpRequest<AppState>, trust_forwarded_for: bool) -> Option<String> {
+    let forwarded = req.headers().contains_key(header::FORWARDED)
+        || req.headers().contains_key(X_FORWARDED_FOR);
+    if trust_forwarded_for && forwarded {
+        if let Some(remote) = req.connection_info().remote() {
+            return Some(remote.to_string());
+        }
     }
+
+    req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+fn rejection_error(rejection: Rejection) -> actix_web::Error {
+    match rejection {
+        Rejection::Banned { .. } => actix_web::error::ErrorForbidden(rejection.to_string()),
+        _ => InternalError::new(rejection.to_string(), StatusCode::TOO_MANY_REQUESTS).into(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    version: &'static str,
+    uptime_secs: u64,
+    /// Game loop iterations over the last second.
+    tick_rate: f64,
+    #[serde(flatten)]
+    game: GameStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpectateQuery {
+    room: Option<String>,
 }
 
 pub fn spectate_handler(
-    (req, state): (HttpRequest<AppState>, State<AppState>),
+    (req, state, query): (HttpRequest<AppState>, State<AppState>, Query<SpectateQuery>),
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    // TODO(bschwind) - Make a separate spectator actor
+    let room = query.room.clone().unwrap_or_else(|| DEFAULT_ROOM.to_string());
+
     actix_web::ws::start(
         &req,
-        ClientWsActor::new(
+        SpectatorWsActor::new(
             state.game_addr.clone(),
-            "SPECTATOR".to_string(),
-            "SPECTATOR".to_string(),
+            state.matchmaker_addr.clone(),
+            room,
+            state.metrics.clone(),
         ),
     )
 }
 
-pub fn reset_handler(
-    (_req, state): (HttpRequest<AppState>, State<AppState>),
+pub fn metrics_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    let metrics = state.metrics.clone();
+
+    state
+        .game_addr
+        .send(ListConnections)
+        .from_err()
+        .map(move |connections| {
+            let queue_depths: Vec<_> =
+                connections.iter().map(|c| (c.player_id, c.outbound_queue)).collect();
+
+            HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(metrics.render(&queue_depths))
+        })
+        .responder()
+}
+
+/// Fails with 503 if the main game loop is stuck or has stopped.
+pub fn health_handler(state: State<AppState>) -> HttpResponse {
+    match state.metrics.since_last_tick() {
+        Some(age) if age <= MAX_TICK_AGE => HttpResponse::Ok().body("ok"),
+        Some(age) => HttpResponse::ServiceUnavailable()
+            .body(format!("The game loop hasn't ticked for {:.1} seconds", age.as_secs_f64())),
+        None => HttpResponse::ServiceUnavailable().body("The game loop hasn't ticked yet"),
+    }
+}
+
+pub fn status_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    let metrics = state.metrics.clone();
+    let uptime = state.started_at.elapsed();
+
+    state
+        .game_addr
+        .send(GetStatus)
+        .from_err()
+        .map(move |game| {
+            HttpResponse::Ok().json(ServerStatus {
+                version: env!("CARGO_PKG_VERSION"),
+                uptime_secs: uptime.as_secs(),
+                tick_rate: metrics.tick_rate(),
+                game,
+            })
+        })
+        .responder()
+}
+
+pub fn replays_handler(state: State<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let replays = match state.config.get().replay_dir {
+        Some(ref dir) => {
+            replay::list_replays(dir).map_err(actix_web::error::ErrorInternalServerError)?
+        },
+        None => vec![],
+    };
+
+    Ok(HttpResponse::Ok().json(replays))
+}
+
+pub fn replay_download_handler(
+    (state, id): (State<AppState>, Path<String>),
+) -> Result<NamedFile, actix_web::Error> {
+    let path = state
+        .config
+        .get()
+        .replay_dir
+        .as_ref()
+        .and_then(|dir| replay::replay_path(dir, &id))
+        .ok_or_else(|| actix_web::error::ErrorNotFound("No such replay"))?;
+
+    Ok(NamedFile::open(path)?)
+}
+
+pub fn replay_handler(
+    (req, state, id): (HttpRequest<AppState>, State<AppState>, Path<String>),
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    state.game_addr.do_send(ServerCommand::Reset);
-    Ok(actix_web::HttpResponse::with_body(StatusCode::OK, "done"))
+    let path = state
+        .config
+        .get()
+        .replay_dir
+        .as_ref()
+        .and_then(|dir| replay::replay_path(dir, &id))
+        .filter(|path| path.exists())
+        .ok_or_else(|| actix_web::error::ErrorNotFound("No such replay"))?;
+
+    let replay = Replay::load(&path).map_err(actix_web::error::ErrorInternalServerError)?;
+    if replay.states.is_empty() {
+        return Err(actix_web::error::ErrorNotFound("This replay has no states yet"));
+    }
+
+    actix_web::ws::start(&req, ReplayWsActor::new(id.into_inner(), replay))
+}
+
+pub fn tournament_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .tournament_addr
+        .send(GetTournament)
+        .from_err()
+        .map(|status| HttpResponse::Ok().json(status))
+        .responder()
+}
+
+pub fn ratings_handler(state: State<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.ratings.leaderboard())
+}
+
+pub fn matchmaking_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .matchmaker_addr
+        .send(GetMatchmaking)
+        .from_err()
+        .map(|status| HttpResponse::Ok().json(status))
+        .responder()
+}
+
+pub fn state_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .game_addr
+        .send(GetSnapshot)
+        .from_err()
+        .map(|snapshot| match snapshot {
+            Some(snapshot) => {
+                HttpResponse::Ok().header(ALLOW_ANY_ORIGIN.0, ALLOW_ANY_ORIGIN.1).json(snapshot)
+            },
+            None => HttpResponse::ServiceUnavailable().body("The game hasn't started yet"),
+        })
+        .responder()
+}
+
+pub fn scoreboard_handler(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .game_addr
+        .send(GetScoreboard)
+        .from_err()
+        .map(|scoreboard| {
+            HttpResponse::Ok().header(ALLOW_ANY_ORIGIN.0, ALLOW_ANY_ORIGIN.1).json(scoreboard)
+        })
+        .responder()
+}
+
+/// Server-sent events with the state a few times a second.
+pub fn state_stream_handler(state: State<AppState>) -> HttpResponse {
+    event_stream(&state, ApiFeed::State)
+}
+
+/// Server-sent events with the scoreboard whenever it changes.
+pub fn scoreboard_stream_handler(state: State<AppState>) -> HttpResponse {
+    event_stream(&state, ApiFeed::Scoreboard)
+}
+
+fn event_stream(state: &State<AppState>, feed: ApiFeed) -> HttpResponse {
+    let (sender, receiver) = mpsc::channel(EVENT_STREAM_BUFFER);
+    state.game_addr.do_send(ApiSubscribe { feed, sender });
+
+    let events = receiver
+        .map(|json| Bytes::from(format!("data: {}\n\n", json)))
+        .map_err(|_| actix_web::error::ErrorInternalServerError("The game went away"));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header(ALLOW_ANY_ORIGIN.0, ALLOW_ANY_ORIGIN.1)
+        .streaming(events)
 }
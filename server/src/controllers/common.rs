@@ -1,8 +1,10 @@
 use crate::AppState;
-use actix_web::{fs::NamedFile, HttpRequest};
+use actix_web::{error::ErrorNotFound, fs::NamedFile, HttpRequest};
 use std::path::PathBuf;
 
 pub fn index_handler(req: HttpRequest<AppState>) -> actix_web::Result<NamedFile> {
     let _path: PathBuf = req.match_info().query("tail").unwrap_or_else(|_| "".into());
-    Ok(NamedFile::open("../spectator/index.html")?)
+    let spectator_dir =
+        req.state().spectator_dir.as_ref().ok_or_else(|| ErrorNotFound("No spectator page"))?;
+    Ok(NamedFile::open(spectator_dir.join("index.html"))?)
 }
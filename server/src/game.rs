@@ -1,9 +1,9 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::time::Instant;
-use std::time::{Duration, SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
 use tokyo::models::{
-    BulletState, DeadPlayer, GameCommand, GameConfig, GameState, Item, PlayerState,
+    BulletState, DeadPlayer, GameCommand, GameConfig, GameState, Item, ItemType, PlayerState,
     PLAYER_BASE_SPEED,
 };
 
@@ -11,6 +11,10 @@ const DEAD_PUNISH: Duration = Duration::from_secs(3);
 
 pub const TICKS_PER_SECOND: f32 = 30.0;
 
+// Bounds for the game loop time scale, 1.0 being real time
+pub const MIN_TIME_SCALE: f32 = 0.05;
+pub const MAX_TIME_SCALE: f32 = 8.0;
+
 const MAX_CONCURRENT_ITEMS: usize = 20;
 const ITEM_SPAWN_TIME: Duration = Duration::from_secs(5);
 
@@ -91,14 +95,51 @@ impl Triangle for Item {
     }
 }
 
+/// Notable things that happened during a tick, drained by the game loop with
+/// `Game::drain_events`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    Kill {
+        killer: u32,
+        victim: u32,
+        // None when the two ships crashed into each other
+        bullet_id: Option<u32>,
+        killer_position: Option<(f32, f32)>,
+        victim_position: (f32, f32),
+    },
+    ItemPickup {
+        player: u32,
+        item_id: u32,
+        item_type: ItemType,
+    },
+}
+
+impl GameEvent {
+    pub fn involves(&self, player_id: u32) -> bool {
+        match *self {
+            GameEvent::Kill { killer, victim, .. } => killer == player_id || victim == player_id,
+            GameEvent::ItemPickup { player, .. } => player == player_id,
+        }
+    }
+}
+
 pub struct Game {
     config: GameConfig,
     pub state: GameState,
     rng: rand::rngs::ThreadRng,
     bullet_id_counter: u32,
     item_id_counter: u32,
-    survival_times: HashMap<u32, Instant>,
-    last_item_spawn_at: Instant,
+    // All timers below are measured in game time (see `clock`) rather than
+    // wall time, so they freeze while paused and follow the time scale.
+    clock: Duration,
+    survival_times: HashMap<u32, Duration>,
+    respawn_times: HashMap<u32, Duration>,
+    last_item_spawn_at: Duration,
+    // Players whose socket dropped, kept out of the arena until they
+    // reconnect or their grace period runs out.
+    inactive: HashMap<u32, PlayerState>,
+    events: Vec<GameEvent>,
 }
 
 impl Game {
@@ -108,9 +149,13 @@ impl Game {
             rng: Default::default(),
             bullet_id_counter: 0,
             item_id_counter: 0,
+            clock: Duration::from_secs(0),
             survival_times: HashMap::new(),
+            respawn_times: HashMap::new(),
             config,
-            last_item_spawn_at: Instant::now(),
+            last_item_spawn_at: Duration::from_secs(0),
+            inactive: HashMap::new(),
+            events: Vec::new(),
         }
     }
 
@@ -122,9 +167,26 @@ impl Game {
         for corpse in self.state.dead.iter() {
             new.add_player(corpse.player.id);
         }
+        for player_id in self.inactive.keys() {
+            new.add_player(*player_id);
+            new.player_disconnected(*player_id);
+        }
+        // Keep counting ticks so they stay unique across resets
+        new.state.tick = self.state.tick;
         let _ = std::mem::replace(self, new);
     }
 
+    pub fn set_config(&mut self, config: GameConfig) {
+        self.config = config;
+        self.state.bounds = self.bounds();
+
+        // Pull everyone back inside the new arena
+        for player in &mut self.state.players {
+            player.x = player.x.max(player.radius).min(config.bound_x - player.radius);
+            player.y = player.y.max(player.radius).min(config.bound_y - player.radius);
+        }
+    }
+
     fn bounds(&self) -> (f32, f32) {
         (self.config.bound_x, self.config.bound_y)
     }
@@ -134,8 +196,7 @@ impl Game {
         let bounds = self.bounds();
         player.randomize(&mut self.rng, bounds);
         self.state.players.push(player);
-        self.survival_times
-            .insert(player_id, Instant::now() + Duration::from_secs(SURVIVAL_TIMEOUT));
+        self.survival_times.insert(player_id, self.clock + Duration::from_secs(SURVIVAL_TIMEOUT));
     }
 
     pub fn player_left(&mut self, player_id: u32) {
@@ -150,6 +211,48 @@ impl Game {
 
         self.state.scoreboard.remove(&player_id);
         self.survival_times.remove(&player_id);
+        self.respawn_times.remove(&player_id);
+        self.inactive.remove(&player_id);
+    }
+
+    /// Takes the player out of the arena while keeping their score, so they
+    /// can pick up where they left off with `player_reconnected`.
+    pub fn player_disconnected(&mut self, player_id: u32) {
+        info!("Player {} disconnected", player_id);
+
+        let player = if let Some(idx) = self.state.players.iter().position(|p| p.id == player_id) {
+            Some(self.state.players.remove(idx))
+        } else if let Some(idx) = self.state.dead.iter().position(|p| p.player.id == player_id) {
+            Some(self.state.dead.remove(idx).player)
+        } else {
+            None
+        };
+
+        if let Some(player) = player {
+            self.inactive.insert(player_id, player);
+        }
+        self.survival_times.remove(&player_id);
+        self.respawn_times.remove(&player_id);
+    }
+
+    pub fn player_reconnected(&mut self, player_id: u32) {
+        info!("Player {} reconnected", player_id);
+
+        match self.inactive.remove(&player_id) {
+            Some(player) => {
+                self.state.players.push(player);
+                self.survival_times
+                    .insert(player_id, self.clock + Duration::from_secs(SURVIVAL_TIMEOUT));
+            },
+            // We lost track of them somehow, make sure they end up in the arena
+            None => {
+                let known = self.state.players.iter().any(|p| p.id == player_id)
+                    || self.state.dead.iter().any(|p| p.player.id == player_id);
+                if !known {
+                    self.add_player(player_id);
+                }
+            },
+        }
     }
 
     pub fn handle_cmd(&mut self, player_id: u32, cmd: GameCommand) {
@@ -198,28 +301,68 @@ impl Game {
 
     pub fn init(&mut self) {}
 
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn kill(
+        &mut self,
+        mut player: PlayerState,
+        killer: u32,
+        bullet_id: Option<u32>,
+        killer_position: Option<(f32, f32)>,
+    ) {
+        self.events.push(GameEvent::Kill {
+            killer,
+            victim: player.id,
+            bullet_id,
+            killer_position,
+            victim_position: (player.x, player.y),
+        });
+
+        let bounds = self.bounds();
+        player.randomize(&mut self.rng, bounds);
+
+        self.respawn_times.insert(player.id, self.clock + DEAD_PUNISH);
+        self.state.dead.push(DeadPlayer {
+            respawn: SystemTime::now() + DEAD_PUNISH,
+            respawn_in: DEAD_PUNISH,
+            player,
+            killer,
+        });
+    }
+
     pub fn tick(&mut self, dt: f32) {
+        self.clock += Duration::from_secs_f32(dt);
+        self.state.tick += 1;
+
         let bounds = self.bounds();
         // Revive the dead
-        let now = SystemTime::now();
-        let revived =
-            self.state.dead.extract_if(|corpse| corpse.respawn <= now).map(|dead| dead.player).map(
-                |player| {
-                    println!("revived player {}", player.id);
-                    player
-                },
-            );
+        let now = self.clock;
+        let respawn_times = &self.respawn_times;
+        let revived = self
+            .state
+            .dead
+            .extract_if(.., |corpse| respawn_times.get(&corpse.player.id).is_none_or(|t| *t <= now))
+            .map(|dead| dead.player)
+            .inspect(|player| info!("revived player {}", player.id));
 
         self.state.players.extend(revived);
+        let wall_now = SystemTime::now();
+        for corpse in self.state.dead.iter_mut() {
+            corpse.respawn_in =
+                respawn_times.get(&corpse.player.id).map_or(Duration::from_secs(0), |t| *t - now);
+            corpse.respawn = wall_now + corpse.respawn_in;
+        }
 
-        if self.last_item_spawn_at.elapsed() > ITEM_SPAWN_TIME
+        if self.clock - self.last_item_spawn_at > ITEM_SPAWN_TIME
             && self.state.items.len() < MAX_CONCURRENT_ITEMS
         {
             let item_id = self.item_id_counter;
             self.item_id_counter = self.item_id_counter.wrapping_add(1);
             self.state.items.push(Item::new_randomized(item_id, &mut self.rng, bounds));
 
-            self.last_item_spawn_at = Instant::now();
+            self.last_item_spawn_at = self.clock;
         }
 
         // Advance bullets
@@ -267,6 +410,8 @@ impl Game {
         self.state.bullets.retain(|b| !colliding_buf.contains(&b.id));
 
         let mut killer_map = HashMap::new();
+        let positions: HashMap<u32, (f32, f32)> =
+            self.state.players.iter().map(|p| (p.id, (p.x, p.y))).collect();
 
         // count collisions
         let mut colliding_buf = HashSet::new();
@@ -281,29 +426,31 @@ impl Game {
             }
         }
 
-        for mut player in self.state.players.extract_if(|player| colliding_buf.contains(&player.id))
-        {
-            player.randomize(&mut self.rng, bounds);
-            self.state.dead.push(DeadPlayer {
-                respawn: SystemTime::now() + DEAD_PUNISH,
-                player,
-                killer: killer_map.get(&player.id).unwrap_or(0),
-            });
+        let crashed: Vec<_> = self
+            .state
+            .players
+            .extract_if(.., |player| colliding_buf.contains(&player.id))
+            .collect();
+        for player in crashed {
+            let killer = killer_map.get(&player.id).cloned().unwrap_or(0);
+            self.kill(player, killer, None, positions.get(&killer).cloned());
         }
 
         // count the dead
         let mut hits = vec![];
         let mut used_bullets = vec![];
-        let bounds = self.bounds();
+        let mut deceased_players = vec![];
+        let mut bullet_map = HashMap::new();
 
         for bullet in &mut self.state.bullets {
-            let deceased = self.state.players.extract_if(|player| {
+            let deceased = self.state.players.extract_if(.., |player| {
                 if player.is_colliding(bullet) && bullet.player_id != player.id {
-                    println!(
+                    info!(
                         "Player {} killed player {} at ({}, {})",
                         bullet.player_id, player.id, bullet.x, bullet.y
                     );
                     killer_map.insert(player.id, bullet.player_id);
+                    bullet_map.insert(player.id, bullet.id);
                     hits.push(bullet.player_id);
                     used_bullets.push(bullet.id);
 
@@ -312,18 +459,17 @@ impl Game {
                     false
                 }
             });
-            for mut player in deceased {
-                // Reset their survival time bonus
-                self.survival_times
-                    .insert(player.id, Instant::now() + Duration::from_secs(SURVIVAL_TIMEOUT));
-
-                player.randomize(&mut self.rng, bounds);
-                self.state.dead.push(DeadPlayer {
-                    respawn: SystemTime::now() + DEAD_PUNISH,
-                    player,
-                    killer: killer_map.get(&player.id).unwrap_or(0),
-                });
-            }
+            deceased_players.extend(deceased);
+        }
+
+        for player in deceased_players {
+            // Reset their survival time bonus
+            self.survival_times
+                .insert(player.id, self.clock + Duration::from_secs(SURVIVAL_TIMEOUT));
+
+            let killer = killer_map.get(&player.id).cloned().unwrap_or(0);
+            let bullet_id = bullet_map.get(&player.id).cloned();
+            self.kill(player, killer, bullet_id, positions.get(&killer).cloned());
         }
 
         let mut used_items = vec![];
@@ -332,6 +478,11 @@ impl Game {
                 if player.is_colliding(item) {
                     item.apply_to(player);
                     used_items.push(item.id);
+                    self.events.push(GameEvent::ItemPickup {
+                        player: player.id,
+                        item_id: item.id,
+                        item_type: item.item_type.clone(),
+                    });
                 }
             }
         }
@@ -347,13 +498,13 @@ impl Game {
 
         // Reward players for staying alive
         for (player_id, next_reward_time) in &mut self.survival_times {
-            if *next_reward_time <= Instant::now() {
+            if *next_reward_time <= self.clock {
                 // Only reward if there is more than 1 player in the game
                 if self.state.players.len() > 1 {
                     *self.state.scoreboard.entry(*player_id).or_default() += 1;
                 }
 
-                *next_reward_time = Instant::now() + Duration::from_secs(SURVIVAL_POINT_INTERVAL);
+                *next_reward_time = self.clock + Duration::from_secs(SURVIVAL_POINT_INTERVAL);
             }
         }
     }
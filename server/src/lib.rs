@@ -0,0 +1,428 @@
+//! The tokyo-rs game server. `tokyo-server` runs one from a config file,
+//! and `Server::builder` starts any number of them from code, e.g. in tests:
+//!
+//! ```no_run
+//! use tokyo_server::{AppConfig, GameConfig, Server};
+//!
+//! let mut config = AppConfig::new(GameConfig { bound_x: 3500.0, bound_y: 2500.0 });
+//! config.dev_mode = true;
+//!
+//! let server = Server::builder(config).bind("127.0.0.1:0").start().unwrap();
+//! println!("Connect to ws://{}/socket", server.addr());
+//! server.shutdown();
+//! ```
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod abuse;
+mod actors;
+mod audit;
+mod bots;
+pub mod config;
+mod controllers;
+mod game;
+mod metrics;
+mod models;
+mod ratings;
+mod registry;
+mod replay;
+mod tournament;
+
+pub use crate::config::AppConfig;
+pub use tokyo::models::GameConfig;
+
+use crate::{
+    abuse::Guard,
+    actors::{GameActor, MatchmakerActor, SignalActor, TournamentActor, WebhookActor},
+    audit::AuditLog,
+    config::SharedConfig,
+    metrics::Metrics,
+    models::{messages::Shutdown, spectator::DEFAULT_ROOM},
+    ratings::Ratings,
+    registry::Registry,
+};
+use actix::{Actor, Addr, System};
+use actix_web::{fs::StaticFiles, http::Method, middleware::Logger, server, App};
+use failure::{format_err, Error};
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+// How long in-flight HTTP requests get to finish during a shutdown
+const SHUTDOWN_TIMEOUT_SECS: u16 = 5;
+
+const DEFAULT_PORT: u16 = 3000;
+
+pub struct AppState {
+    game_addr: Addr<GameActor>,
+    matchmaker_addr: Addr<MatchmakerActor>,
+    tournament_addr: Addr<TournamentActor>,
+    config: SharedConfig,
+    registry: Registry,
+    ratings: Ratings,
+    guard: Guard,
+    metrics: Arc<Metrics>,
+    started_at: Instant,
+    spectator_dir: Option<PathBuf>,
+}
+
+/// A server running on a thread of its own, with its own actor system, game
+/// loop and listeners, so several can run side by side in one process. It
+/// keeps running if this is dropped, until it's shut down through a
+/// `ServerHandle`.
+#[derive(Debug)]
+pub struct Server {
+    addr: SocketAddr,
+    tls_addr: Option<SocketAddr>,
+    handle: ServerHandle,
+    thread: JoinHandle<()>,
+}
+
+impl Server {
+    pub fn builder(config: AppConfig) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            config_path: None,
+            bind: None,
+            listener: None,
+            spectator_dir: None,
+            handle_signals: false,
+        }
+    }
+
+    /// Like `builder`, with the config read from a file. Reloading the
+    /// config, through SIGHUP or the admin API, reads the file again.
+    pub fn from_config_file(path: PathBuf) -> Result<ServerBuilder, Error> {
+        let config = AppConfig::load(&path)?;
+
+        Ok(ServerBuilder { config_path: Some(path), ..Server::builder(config) })
+    }
+
+    /// The address of the plain HTTP and websocket listener.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The address of the HTTPS and WSS listener, if TLS is configured.
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls_addr
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Blocks until the server has stopped.
+    pub fn wait(self) {
+        if self.thread.join().is_err() {
+            error!("The server thread panicked");
+        }
+    }
+
+    /// Shuts the server down gracefully and waits for it to stop.
+    pub fn shutdown(self) {
+        self.handle.shutdown();
+        self.wait();
+    }
+}
+
+/// Stops a running server from anywhere.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    signal_addr: Addr<SignalActor>,
+}
+
+impl ServerHandle {
+    /// Tells every client the server is going down, flushes ratings, replays
+    /// and the audit log, then stops the server. Returns right away.
+    pub fn shutdown(&self) {
+        self.signal_addr.do_send(Shutdown);
+    }
+}
+
+pub struct ServerBuilder {
+    config: AppConfig,
+    config_path: Option<PathBuf>,
+    bind: Option<String>,
+    listener: Option<TcpListener>,
+    spectator_dir: Option<PathBuf>,
+    handle_signals: bool,
+}
+
+impl ServerBuilder {
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    pub fn game_config(mut self, game_config: GameConfig) -> ServerBuilder {
+        self.config.game_config = game_config;
+        self
+    }
+
+    /// Where to listen for HTTP and websockets, `0.0.0.0:<server_port>` by
+    /// default. Use port 0 to get a free port, then ask `Server::addr`.
+    pub fn bind<A: Into<String>>(mut self, addr: A) -> ServerBuilder {
+        self.bind = Some(addr.into());
+        self
+    }
+
+    /// Serves HTTP and websockets on an already bound listener instead.
+    pub fn listener(mut self, listener: TcpListener) -> ServerBuilder {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Serves the spectator page from this directory. Without it only the
+    /// websocket and HTTP APIs are served.
+    pub fn spectator_dir<P: Into<PathBuf>>(mut self, dir: P) -> ServerBuilder {
+        self.spectator_dir = Some(dir.into());
+        self
+    }
+
+    /// Reloads the config on SIGHUP and shuts down on SIGINT, SIGTERM and
+    /// SIGQUIT. Off by default, as signals are for the whole process.
+    pub fn handle_signals(mut self, handle_signals: bool) -> ServerBuilder {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// Binds the listeners and starts the game, returning once the server
+    /// is accepting connections.
+    pub fn start(self) -> Result<Server, Error> {
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let thread = thread::Builder::new().name("tokyo-server".to_string()).spawn(move || {
+            let actor_system = System::new("meetup-server");
+
+            match self.run() {
+                Ok(started) => {
+                    let _ = started_tx.send(Ok(started));
+                    let _ = actor_system.run();
+                },
+                Err(e) => {
+                    let _ = started_tx.send(Err(e));
+                },
+            }
+        })?;
+
+        let (addr, tls_addr, handle) = started_rx
+            .recv()
+            .map_err(|_| format_err!("The server thread panicked while starting"))??;
+
+        Ok(Server { addr, tls_addr, handle, thread })
+    }
+
+    // Starts the actors and listeners on the actor system of this thread
+    fn run(self) -> Result<(SocketAddr, Option<SocketAddr>, ServerHandle), Error> {
+        let ServerBuilder { config, config_path, bind, listener, spectator_dir, handle_signals } =
+            self;
+        let config = SharedConfig::new(config, config_path);
+
+        let bind = bind.unwrap_or_else(|| {
+            format!("0.0.0.0:{}", config.get().server_port.unwrap_or(DEFAULT_PORT))
+        });
+        let tls_acceptor = match config.get().tls {
+            Some(ref tls) => Some((tls.port, tls.acceptor()?)),
+            None => None,
+        };
+        if let Some(ref dir) = spectator_dir {
+            if !dir.is_dir() {
+                return Err(format_err!("Spectator directory {} not found", dir.display()));
+            }
+        }
+
+        let registry = Registry::load(config.get().registry_path.clone())?;
+        let ratings = Ratings::load(config.get().ratings_path.clone())?;
+
+        let started_at = Instant::now();
+        let metrics = Arc::new(Metrics::default());
+        let guard = Guard::new(config.clone());
+
+        let audit_log = AuditLog::open(config.get().audit_log_path.as_deref())?;
+
+        let webhook_addr = WebhookActor::new(config.clone()).start();
+
+        let game_actor = GameActor::new(
+            DEFAULT_ROOM.to_string(),
+            config.clone(),
+            metrics.clone(),
+            audit_log.clone(),
+            ratings.clone(),
+            webhook_addr.clone(),
+        );
+        let game_actor_addr = game_actor.start();
+        let server_game_addr = game_actor_addr.clone();
+        let tournament_addr = TournamentActor::new(game_actor_addr.clone()).start();
+        let matchmaker_addr =
+            MatchmakerActor::new(config.clone(), audit_log, ratings.clone(), webhook_addr).start();
+        let server_matchmaker_addr = matchmaker_addr.clone();
+
+        let mut server = server::new(move || {
+            let app_state = AppState {
+                game_addr: server_game_addr.clone(),
+                matchmaker_addr: server_matchmaker_addr.clone(),
+                tournament_addr: tournament_addr.clone(),
+                config: config.clone(),
+                registry: registry.clone(),
+                ratings: ratings.clone(),
+                guard: guard.clone(),
+                metrics: metrics.clone(),
+                started_at,
+                spectator_dir: spectator_dir.clone(),
+            };
+
+            let app = App::with_state(app_state)
+                .middleware(Logger::default())
+                .resource("/socket", |r| {
+                    r.method(Method::GET).with(controllers::api::socket_handler);
+                })
+                .resource("/spectate", |r| {
+                    r.method(Method::GET).with(controllers::api::spectate_handler);
+                })
+                .resource("/metrics", |r| {
+                    r.method(Method::GET).with(controllers::api::metrics_handler);
+                })
+                .resource("/api/state", |r| {
+                    r.method(Method::GET).with(controllers::api::state_handler);
+                })
+                .resource("/api/state/stream", |r| {
+                    r.method(Method::GET).with(controllers::api::state_stream_handler);
+                })
+                .resource("/api/scoreboard", |r| {
+                    r.method(Method::GET).with(controllers::api::scoreboard_handler);
+                })
+                .resource("/api/scoreboard/stream", |r| {
+                    r.method(Method::GET).with(controllers::api::scoreboard_stream_handler);
+                })
+                .resource("/healthz", |r| {
+                    r.method(Method::GET).with(controllers::api::health_handler);
+                })
+                .resource("/status", |r| {
+                    r.method(Method::GET).with(controllers::api::status_handler);
+                })
+                .resource("/replays", |r| {
+                    r.method(Method::GET).with(controllers::api::replays_handler);
+                })
+                .resource("/replays/{id}", |r| {
+                    r.method(Method::GET).with(controllers::api::replay_download_handler);
+                })
+                .resource("/replay/{id}", |r| {
+                    r.method(Method::GET).with(controllers::api::replay_handler);
+                })
+                .resource("/ratings", |r| {
+                    r.method(Method::GET).with(controllers::api::ratings_handler);
+                })
+                .resource("/tournament", |r| {
+                    r.method(Method::GET).with(controllers::api::tournament_handler);
+                })
+                .resource("/matchmaking", |r| {
+                    r.method(Method::GET).with(controllers::api::matchmaking_handler);
+                })
+                .resource("/reset", |r| {
+                    r.method(Method::GET).with(controllers::admin::reset_handler);
+                })
+                .resource("/admin/connections", |r| {
+                    r.method(Method::GET).with(controllers::admin::connections_handler);
+                })
+                .resource("/admin/kick/{key}", |r| {
+                    r.method(Method::POST).with(controllers::admin::kick_handler);
+                })
+                .resource("/admin/ban/{key}", |r| {
+                    r.method(Method::POST).with(controllers::admin::ban_handler);
+                    r.method(Method::DELETE).with(controllers::admin::unban_handler);
+                })
+                .resource("/admin/abuse", |r| {
+                    r.method(Method::GET).with(controllers::admin::abuse_handler);
+                })
+                .resource("/admin/pause", |r| {
+                    r.method(Method::POST).with(controllers::admin::pause_handler);
+                })
+                .resource("/admin/resume", |r| {
+                    r.method(Method::POST).with(controllers::admin::resume_handler);
+                })
+                .resource("/admin/step", |r| {
+                    r.method(Method::POST).with(controllers::admin::step_handler);
+                })
+                .resource("/admin/time-scale/{scale}", |r| {
+                    r.method(Method::POST).with(controllers::admin::time_scale_handler);
+                })
+                .resource("/admin/reset", |r| {
+                    r.method(Method::POST).with(controllers::admin::reset_handler);
+                })
+                .resource("/admin/config", |r| {
+                    r.method(Method::GET).with(controllers::admin::get_config_handler);
+                    r.method(Method::PUT).with(controllers::admin::update_config_handler);
+                })
+                .resource("/admin/config/reload", |r| {
+                    r.method(Method::POST).with(controllers::admin::reload_config_handler);
+                })
+                .resource("/admin/tournament", |r| {
+                    r.method(Method::POST).with(controllers::admin::start_tournament_handler);
+                    r.method(Method::DELETE).with(controllers::admin::cancel_tournament_handler);
+                })
+                .resource("/admin/teams", |r| {
+                    r.method(Method::GET).with(controllers::admin::teams_handler);
+                })
+                .resource("/admin/teams/{key}", |r| {
+                    r.method(Method::PUT).with(controllers::admin::put_team_handler);
+                    r.method(Method::DELETE).with(controllers::admin::delete_team_handler);
+                });
+
+            match spectator_dir {
+                Some(ref dir) => app
+                    .handler(
+                        "/",
+                        StaticFiles::new(dir)
+                            .expect("the spectator directory was checked up front")
+                            .index_file("index.html"),
+                    )
+                    .resource("/{tail:.*}j", |r| {
+                        r.method(Method::GET).with(controllers::common::index_handler)
+                    }),
+                None => app,
+            }
+        })
+        // Signals are handled by the SignalActor, which notifies clients before
+        // stopping the server.
+        .disable_signals()
+        .shutdown_timeout(SHUTDOWN_TIMEOUT_SECS);
+
+        server = match listener {
+            Some(listener) => server.listen(listener),
+            None => {
+                server.bind(&bind).map_err(|e| format_err!("Failed to bind {}: {}", bind, e))?
+            },
+        };
+
+        // HTTPS and WSS run next to the plain listener
+        if let Some((tls_port, acceptor)) = tls_acceptor {
+            let tls_bind = format!("0.0.0.0:{}", tls_port);
+            server = server
+                .bind_ssl(&tls_bind, acceptor)
+                .map_err(|e| format_err!("Failed to bind {}: {}", tls_bind, e))?;
+        }
+
+        let addrs = server.addrs_with_scheme();
+        let addr = addrs
+            .iter()
+            .find(|(_, scheme)| *scheme == "http")
+            .map(|(addr, _)| *addr)
+            .ok_or_else(|| format_err!("The server has no HTTP listener"))?;
+        let tls_addr = addrs.iter().find(|(_, scheme)| *scheme == "https").map(|(addr, _)| *addr);
+
+        let server_addr = server.start();
+
+        let signal_addr =
+            SignalActor::new(game_actor_addr, matchmaker_addr, server_addr, handle_signals).start();
+
+        Ok((addr, tls_addr, ServerHandle { signal_addr }))
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Counters and gauges shared between the game loop and the actors, rendered
+/// in the Prometheus text format by the `/metrics` endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub ticks_total: AtomicU64,
+    pub tick_overruns_total: AtomicU64,
+    // Durations and rates are stored as f64 bits
+    tick_duration_seconds: AtomicU64,
+    tick_work_seconds: AtomicU64,
+    tick_rate: AtomicU64,
+    // Milliseconds since the Unix epoch, 0 before the first tick
+    last_tick_millis: AtomicU64,
+    pub players: AtomicUsize,
+    pub dead_players: AtomicUsize,
+    pub spectators: AtomicUsize,
+    pub bullets: AtomicUsize,
+    pub items: AtomicUsize,
+    pub messages_received_total: AtomicU64,
+    pub messages_sent_total: AtomicU64,
+    pub rate_limited_total: AtomicU64,
+    pub penalties_total: AtomicU64,
+    serialization_nanos_total: AtomicU64,
+    pub serializations_total: AtomicU64,
+}
+
+fn store_f64(atomic: &AtomicU64, value: f64) {
+    atomic.store(value.to_bits(), Ordering::Relaxed);
+}
+
+fn load_f64(atomic: &AtomicU64) -> f64 {
+    f64::from_bits(atomic.load(Ordering::Relaxed))
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Metrics {
+    /// Records a game loop iteration. `duration` is the time since the previous
+    /// iteration started, `work` the time spent before going to sleep.
+    pub fn record_tick(&self, duration: Duration, work: Duration, target: Duration) {
+        self.ticks_total.fetch_add(1, Ordering::Relaxed);
+        if work > target {
+            self.tick_overruns_total.fetch_add(1, Ordering::Relaxed);
+        }
+
+        store_f64(&self.tick_duration_seconds, duration.as_secs_f64());
+        store_f64(&self.tick_work_seconds, work.as_secs_f64());
+        self.last_tick_millis.store(unix_millis(), Ordering::Relaxed);
+    }
+
+    /// How long ago the game loop last ticked, or None if it never has.
+    pub fn since_last_tick(&self) -> Option<Duration> {
+        match self.last_tick_millis.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(Duration::from_millis(unix_millis().saturating_sub(millis))),
+        }
+    }
+
+    pub fn set_tick_rate(&self, rate: f64) {
+        store_f64(&self.tick_rate, rate);
+    }
+
+    pub fn tick_rate(&self) -> f64 {
+        load_f64(&self.tick_rate)
+    }
+
+    pub fn record_serialization(&self, duration: Duration) {
+        self.serializations_total.fetch_add(1, Ordering::Relaxed);
+        self.serialization_nanos_total.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders all metrics, plus the outbound queue depth of each connected
+    /// player given as `(player_id, depth)`.
+    pub fn render(&self, queue_depths: &[(Option<u32>, usize)]) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("tokyo_ticks_total", "Game loop iterations.", &self.ticks_total),
+            (
+                "tokyo_tick_overruns_total",
+                "Game loop iterations which took longer than the target tick duration.",
+                &self.tick_overruns_total,
+            ),
+            (
+                "tokyo_messages_received_total",
+                "Websocket messages received from players.",
+                &self.messages_received_total,
+            ),
+            (
+                "tokyo_messages_sent_total",
+                "Websocket messages sent to players and spectators.",
+                &self.messages_sent_total,
+            ),
+            (
+                "tokyo_rate_limited_total",
+                "Player commands rejected by the rate limiter.",
+                &self.rate_limited_total,
+            ),
+            (
+                "tokyo_penalties_total",
+                "Warnings, mutes and bans handed out for going over the command budget.",
+                &self.penalties_total,
+            ),
+            (
+                "tokyo_serializations_total",
+                "Outgoing messages serialized to JSON.",
+                &self.serializations_total,
+            ),
+        ];
+        for (name, help, counter) in counters.iter() {
+            write_metric(&mut out, name, help, "counter", counter.load(Ordering::Relaxed));
+        }
+
+        write_metric(
+            &mut out,
+            "tokyo_serialization_seconds_total",
+            "Time spent serializing outgoing messages to JSON.",
+            "counter",
+            self.serialization_nanos_total.load(Ordering::Relaxed) as f64 / 1e9,
+        );
+
+        let float_gauges = [
+            (
+                "tokyo_tick_duration_seconds",
+                "Time between the starts of the last two game loop iterations.",
+                &self.tick_duration_seconds,
+            ),
+            (
+                "tokyo_tick_work_seconds",
+                "Time the last game loop iteration spent before sleeping.",
+                &self.tick_work_seconds,
+            ),
+            ("tokyo_tick_rate", "Game loop iterations per second.", &self.tick_rate),
+        ];
+        for (name, help, gauge) in float_gauges.iter() {
+            write_metric(&mut out, name, help, "gauge", load_f64(gauge));
+        }
+
+        let gauges = [
+            ("tokyo_players", "Players alive in the arena.", &self.players),
+            ("tokyo_dead_players", "Players waiting to respawn.", &self.dead_players),
+            ("tokyo_spectators", "Connected spectators.", &self.spectators),
+            ("tokyo_bullets", "Bullets in the arena.", &self.bullets),
+            ("tokyo_items", "Items in the arena.", &self.items),
+        ];
+        for (name, help, gauge) in gauges.iter() {
+            write_metric(&mut out, name, help, "gauge", gauge.load(Ordering::Relaxed));
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP tokyo_outbound_queue_depth Messages waiting to be sent to a player."
+        );
+        let _ = writeln!(out, "# TYPE tokyo_outbound_queue_depth gauge");
+        for (player_id, depth) in queue_depths {
+            let label = player_id.map(|id| id.to_string()).unwrap_or_default();
+            let _ =
+                writeln!(out, "tokyo_outbound_queue_depth{{player_id=\"{}\"}} {}", label, depth);
+        }
+
+        out
+    }
+}
+
+fn write_metric<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    value: T,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
@@ -1,5 +1,13 @@
-use actix::Message;
-use tokyo::models::GameCommand;
+use crate::{
+    actors::GameActor,
+    models::spectator::PlayerPhase,
+    tournament::{Tournament, TournamentStatus},
+};
+use actix::{Addr, Message};
+use actix_web::ws::CloseCode;
+use futures::sync::mpsc;
+use std::{collections::HashMap, time::Duration};
+use tokyo::models::{GameCommand, GameConfig, GameState};
 
 #[derive(Debug, Message)]
 pub struct PlayerGameCommand {
@@ -7,10 +15,196 @@ pub struct PlayerGameCommand {
     pub cmd: GameCommand,
 }
 
+/// Closes a client socket. Kicks and bans use `CloseCode::Policy` so clients
+/// can tell they shouldn't simply reconnect.
 #[derive(Debug, Message)]
-pub struct ClientStop {}
+pub struct ClientStop {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+impl ClientStop {
+    pub fn kicked(reason: &str) -> ClientStop {
+        ClientStop { code: CloseCode::Policy, reason: reason.to_string() }
+    }
+
+    /// For API keys which aren't playing the current tournament match. They
+    /// can try again later.
+    pub fn not_scheduled() -> ClientStop {
+        ClientStop {
+            code: CloseCode::Again,
+            reason: "Only the players scheduled for this match may join".to_string(),
+        }
+    }
+}
 
+/// Tells the game actor the server is going down: notify and disconnect all
+/// clients, stop the game loop and flush everything to disk.
 #[derive(Debug, Message)]
+pub struct Shutdown;
+
+/// Ends the match of a matchmade room: the players are rated, spectators are
+/// sent back to the main room and the game actor stops. The bots stay
+/// connected, the matchmaker puts them back in the queue.
+#[derive(Debug, Message)]
+pub struct CloseRoom;
+
+#[derive(Debug, Clone, Message, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ServerCommand {
-    Reset
+    Reset,
+    Pause,
+    Resume,
+    Step,
+    SetTimeScale(f32),
+    Kick(String),
+    Ban(String),
+    Unban(String),
+    UpdateTeam(String, String, Option<String>),
+    UpdateGameConfig(GameConfig),
+    /// Resets the game and only lets these API keys play until `EndMatch`.
+    StartMatch(Vec<String>),
+    EndMatch,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct ReloadConfig;
+
+#[derive(Debug, Message)]
+#[rtype(result = "Vec<ConnectionInfo>")]
+pub struct ListConnections;
+
+#[derive(Debug, Message)]
+#[rtype(result = "GameConfig")]
+pub struct GetGameConfig;
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    pub api_key: String,
+    pub player_id: Option<u32>,
+    pub team_name: Option<String>,
+    pub ip: Option<String>,
+    pub rtt_ms: Option<f64>,
+    pub outbound_queue: usize,
+}
+
+/// The score of every API key with a player in the game.
+#[derive(Debug, Message)]
+#[rtype(result = "HashMap<String, u32>")]
+pub struct GetMatchScores;
+
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct StartTournament {
+    pub tournament: Tournament,
+    pub match_duration: Duration,
+    pub intermission: Duration,
+}
+
+#[derive(Debug, Message)]
+pub struct CancelTournament;
+
+#[derive(Debug, Message)]
+#[rtype(result = "Option<TournamentStatus>")]
+pub struct GetTournament;
+
+/// Tells the game actor what to show spectators about the tournament.
+#[derive(Debug, Message)]
+pub struct TournamentUpdate(pub Option<TournamentStatus>);
+
+/// Looks up the game of a matchmade room by name.
+#[derive(Debug, Message)]
+#[rtype(result = "Option<Addr<GameActor>>")]
+pub struct FindRoom(pub String);
+
+#[derive(Debug, Message)]
+#[rtype(result = "MatchmakingStatus")]
+pub struct GetMatchmaking;
+
+#[derive(Debug, Serialize)]
+pub struct MatchmakingStatus {
+    /// Team names of the bots waiting for a match, longest waiting first.
+    pub queued: Vec<String>,
+    pub rooms: Vec<RoomInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomInfo {
+    pub room: String,
+    pub team_names: Vec<String>,
+    pub remaining_secs: u64,
+}
+
+/// Asks a game how it's doing, for the `/status` endpoint.
+#[derive(Debug, Message)]
+#[rtype(result = "GameStatus")]
+pub struct GetStatus;
+
+#[derive(Debug, Serialize)]
+pub struct GameStatus {
+    pub room: String,
+    pub phase: GamePhase,
+    pub tick: u64,
+    pub spectators: usize,
+    /// Best score first.
+    pub players: Vec<PlayerStatus>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    /// Anyone with a valid API key can join.
+    FreeForAll,
+    /// Only the players of a tournament or matchmade match can join.
+    Match,
+    Paused,
+    ShuttingDown,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayerStatus {
+    pub id: u32,
+    pub team_name: Option<String>,
+    pub score: u32,
+    pub phase: PlayerPhase,
+}
+
+/// The latest game state, with the team names of its players.
+#[derive(Debug, Clone, Serialize)]
+pub struct StateSnapshot {
+    #[serde(flatten)]
+    pub state: GameState,
+    pub team_names: HashMap<u32, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScoreboardEntry {
+    /// Players with the same score share a rank.
+    pub rank: usize,
+    pub id: u32,
+    pub team_name: Option<String>,
+    pub score: u32,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "Option<StateSnapshot>")]
+pub struct GetSnapshot;
+
+#[derive(Debug, Message)]
+#[rtype(result = "Vec<ScoreboardEntry>")]
+pub struct GetScoreboard;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiFeed {
+    State,
+    Scoreboard,
+}
+
+/// Streams a feed to an HTTP client as JSON, until the receiving end is
+/// dropped. Snapshots are skipped while the client can't keep up.
+#[derive(Debug, Message)]
+pub struct ApiSubscribe {
+    pub feed: ApiFeed,
+    pub sender: mpsc::Sender<String>,
 }
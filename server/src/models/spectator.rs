@@ -0,0 +1,81 @@
+use crate::{game::GameEvent, ratings::RatingInfo, tournament::TournamentStatus};
+use actix::Message;
+
+/// The room every spectator watches unless it asks for another one.
+pub const DEFAULT_ROOM: &str = "main";
+
+/// Commands a spectator can send over its socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "e", content = "data", rename_all = "lowercase")]
+pub enum SpectatorCommand {
+    /// Watch the room with the given name.
+    Subscribe(String),
+    /// Only receive events involving this player, or every event with `null`.
+    Follow(Option<u32>),
+    /// How many game states to receive per second.
+    Rate(f32),
+}
+
+/// Spectator-only messages. Game states, team names and the like are sent to
+/// spectators in the same `ServerToClient` format the bots get.
+#[derive(Debug, Clone, Message, Serialize)]
+#[serde(tag = "e", content = "data", rename_all = "lowercase")]
+pub enum ServerToSpectator {
+    Subscribed(String),
+    /// The match in this room is over, the spectator is moved to the main
+    /// room.
+    Closed(String),
+    Following(Option<u32>),
+    Rate(f32),
+    Info(SpectatorInfo),
+    Events(Vec<GameEvent>),
+    Replay(ReplayStatus),
+    Tournament(Option<TournamentStatus>),
+    Ratings(Vec<RatingInfo>),
+    Error(String),
+}
+
+/// Playback controls for a `/replay/{id}` socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "e", content = "data", rename_all = "lowercase")]
+pub enum ReplayCommand {
+    Play,
+    Pause,
+    /// Jump to the first state at or after the given tick.
+    Seek(u64),
+    /// Play back at this many times real time.
+    Speed(f32),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayStatus {
+    pub id: String,
+    pub tick: u64,
+    pub first_tick: u64,
+    pub last_tick: u64,
+    pub playing: bool,
+    pub speed: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpectatorInfo {
+    pub room: String,
+    pub spectators: usize,
+    pub players: Vec<PlayerInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerInfo {
+    pub id: u32,
+    pub team_name: Option<String>,
+    pub phase: PlayerPhase,
+    pub rtt_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerPhase {
+    Alive,
+    Dead,
+    Disconnected,
+}
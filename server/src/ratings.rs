@@ -0,0 +1,160 @@
+use failure::{format_err, Error};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+const INITIAL_RATING: f64 = 1500.0;
+
+// The most a rating can move in a single match
+const K_FACTOR: f64 = 32.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rating {
+    /// The team name the key played its last match under.
+    pub team_name: String,
+    pub rating: f64,
+    pub matches: u32,
+    /// Matches finished with the best score.
+    pub wins: u32,
+}
+
+/// A public leaderboard entry, without the API key.
+#[derive(Serialize, Debug, Clone)]
+pub struct RatingInfo {
+    pub team_name: String,
+    pub rating: f64,
+    pub matches: u32,
+    pub wins: u32,
+}
+
+/// How an API key did in a match.
+#[derive(Debug, Clone)]
+pub struct MatchScore {
+    pub team_name: String,
+    pub score: u32,
+}
+
+/// File-backed Elo ratings per API key. A free-for-all match counts as a one
+/// against one game between every pair of players, with the higher score
+/// winning.
+#[derive(Debug, Clone)]
+pub struct Ratings {
+    path: Option<PathBuf>,
+    entries: Arc<RwLock<BTreeMap<String, Rating>>>,
+}
+
+impl Ratings {
+    /// Loads ratings from a JSON file. A missing file means nobody has played
+    /// yet, and without a path ratings only live in memory.
+    pub fn load(path: Option<PathBuf>) -> Result<Ratings, Error> {
+        let entries = match path {
+            Some(ref path) if path.exists() => {
+                let bytes = std::fs::read(path)
+                    .map_err(|e| format_err!("Failed to read ratings {}: {}", path.display(), e))?;
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| format_err!("Failed to parse ratings {}: {}", path.display(), e))?
+            },
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Ratings { path, entries: Arc::new(RwLock::new(entries)) })
+    }
+
+    /// The current rating of an API key, which starts out at the initial
+    /// rating before its first match.
+    pub fn rating(&self, api_key: &str) -> f64 {
+        let entries = self.entries.read().expect("ratings lock should never be poisoned");
+        entries.get(api_key).map_or(INITIAL_RATING, |entry| entry.rating)
+    }
+
+    /// All ratings, best first.
+    pub fn leaderboard(&self) -> Vec<RatingInfo> {
+        let entries = self.entries.read().expect("ratings lock should never be poisoned");
+        let mut leaderboard: Vec<RatingInfo> = entries
+            .values()
+            .map(|entry| RatingInfo {
+                team_name: entry.team_name.clone(),
+                rating: entry.rating.round(),
+                matches: entry.matches,
+                wins: entry.wins,
+            })
+            .collect();
+        leaderboard
+            .sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap_or(std::cmp::Ordering::Equal));
+
+        leaderboard
+    }
+
+    /// Updates ratings from the final scores of a match. Returns false if
+    /// there was nothing to rate: fewer than two players, or nobody scored.
+    pub fn record_match(&self, results: &HashMap<String, MatchScore>) -> Result<bool, Error> {
+        if results.len() < 2 || results.values().all(|result| result.score == 0) {
+            return Ok(false);
+        }
+
+        let mut entries = self.entries.write().expect("ratings lock should never be poisoned");
+        // Only applied once it's saved
+        let mut updated = entries.clone();
+
+        let before: HashMap<&String, f64> = results
+            .keys()
+            .map(|api_key| {
+                let rating = updated.get(api_key).map_or(INITIAL_RATING, |entry| entry.rating);
+                (api_key, rating)
+            })
+            .collect();
+        let best_score = results.values().map(|result| result.score).max().unwrap_or(0);
+        let k = K_FACTOR / (results.len() - 1) as f64;
+
+        for (api_key, result) in results {
+            let mut delta = 0.0;
+            for (other_key, other) in results.iter().filter(|(other_key, _)| *other_key != api_key)
+            {
+                let expected =
+                    1.0 / (1.0 + 10f64.powf((before[other_key] - before[api_key]) / 400.0));
+                let actual = if result.score > other.score {
+                    1.0
+                } else if result.score < other.score {
+                    0.0
+                } else {
+                    0.5
+                };
+                delta += k * (actual - expected);
+            }
+
+            let entry = updated.entry(api_key.clone()).or_insert_with(|| Rating {
+                team_name: result.team_name.clone(),
+                rating: INITIAL_RATING,
+                matches: 0,
+                wins: 0,
+            });
+            entry.team_name = result.team_name.clone();
+            entry.rating += delta;
+            entry.matches += 1;
+            if result.score == best_score {
+                entry.wins += 1;
+            }
+        }
+
+        self.save(&updated)?;
+        *entries = updated;
+
+        Ok(true)
+    }
+
+    fn save(&self, entries: &BTreeMap<String, Rating>) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        // Same as the registry, never leave a half-written file behind
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
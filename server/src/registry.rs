@@ -0,0 +1,131 @@
+use failure::{format_err, Error};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TeamEntry {
+    /// The team that owns this API key.
+    pub team: String,
+    /// The name shown in the arena, regardless of what the client asks for.
+    pub display_name: String,
+    /// A CSS color for the team's ship.
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Returned by `Registry::upsert` when another API key already plays under
+/// the display name.
+#[derive(Debug)]
+pub struct DisplayNameTaken(pub String);
+
+impl fmt::Display for DisplayNameTaken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The name {} belongs to another team", self.0)
+    }
+}
+
+impl std::error::Error for DisplayNameTaken {}
+
+/// A file-backed map of API keys to the teams owning them. Every change is
+/// written back to disk straight away.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    path: Option<PathBuf>,
+    entries: Arc<RwLock<BTreeMap<String, TeamEntry>>>,
+}
+
+impl Registry {
+    /// Loads the registry from a JSON file. A missing file is treated as an
+    /// empty registry and will be created on the first change. Without a path
+    /// the registry only lives in memory.
+    pub fn load(path: Option<PathBuf>) -> Result<Registry, Error> {
+        let entries = match path {
+            Some(ref path) if path.exists() => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    format_err!("Failed to read registry {}: {}", path.display(), e)
+                })?;
+                serde_json::from_slice(&bytes).map_err(|e| {
+                    format_err!("Failed to parse registry {}: {}", path.display(), e)
+                })?
+            },
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Registry { path, entries: Arc::new(RwLock::new(entries)) })
+    }
+
+    pub fn get(&self, api_key: &str) -> Option<TeamEntry> {
+        self.entries.read().expect("registry lock should never be poisoned").get(api_key).cloned()
+    }
+
+    /// Whether a registered team plays under `name`, ignoring case.
+    pub fn is_display_name(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.entries
+            .read()
+            .expect("registry lock should never be poisoned")
+            .values()
+            .any(|entry| entry.display_name.trim().eq_ignore_ascii_case(name))
+    }
+
+    pub fn list(&self) -> BTreeMap<String, TeamEntry> {
+        self.entries.read().expect("registry lock should never be poisoned").clone()
+    }
+
+    /// Adds or replaces the entry for `api_key`. Fails with
+    /// `DisplayNameTaken` if another key already uses the display name,
+    /// ignoring case. Nothing changes unless the file was written.
+    pub fn upsert(&self, api_key: String, entry: TeamEntry) -> Result<(), Error> {
+        let mut entries = self.entries.write().expect("registry lock should never be poisoned");
+        let name = entry.display_name.trim();
+        if entries.iter().any(|(key, other)| {
+            *key != api_key && other.display_name.trim().eq_ignore_ascii_case(name)
+        }) {
+            return Err(DisplayNameTaken(entry.display_name).into());
+        }
+
+        let mut updated = entries.clone();
+        updated.insert(api_key, entry);
+        self.save(&updated)?;
+        *entries = updated;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, api_key: &str) -> Result<Option<TeamEntry>, Error> {
+        let mut entries = self.entries.write().expect("registry lock should never be poisoned");
+        let mut updated = entries.clone();
+        let removed = updated.remove(api_key);
+        if removed.is_some() {
+            self.save(&updated)?;
+            *entries = updated;
+        }
+
+        Ok(removed)
+    }
+
+    fn save(&self, entries: &BTreeMap<String, TeamEntry>) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        // Write to a temporary file first so a crash never leaves a
+        // half-written registry behind.
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use failure::{bail, format_err, Error};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokyo::models::{GameCommand, GameConfig, GameState};
+
+/// Bumped whenever the layout of a replay file changes in a way older
+/// readers can't handle.
+pub const REPLAY_FORMAT_VERSION: u32 = 1;
+
+const REPLAY_EXTENSION: &str = ".replay.gz";
+
+/// The first line of every replay file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub started_at: f64,
+    pub game_config: GameConfig,
+    pub team_names: HashMap<u32, String>,
+    pub team_colors: HashMap<u32, String>,
+}
+
+impl ReplayHeader {
+    pub fn new(
+        game_config: GameConfig,
+        team_names: HashMap<u32, String>,
+        team_colors: HashMap<u32, String>,
+    ) -> ReplayHeader {
+        ReplayHeader {
+            version: REPLAY_FORMAT_VERSION,
+            started_at: unix_time(SystemTime::now()),
+            game_config,
+            team_names,
+            team_colors,
+        }
+    }
+}
+
+/// Every line after the header is one of these.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplayRecord {
+    State(GameState),
+    /// A command accepted from a player, with the last tick before the game
+    /// loop applied it.
+    Command {
+        tick: u64,
+        player_id: u32,
+        command: GameCommand,
+    },
+    TeamNames {
+        tick: u64,
+        team_names: HashMap<u32, String>,
+        team_colors: HashMap<u32, String>,
+    },
+    GameConfig {
+        tick: u64,
+        game_config: GameConfig,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayFile {
+    pub id: String,
+    pub size: u64,
+    pub modified: f64,
+}
+
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map(|since_epoch| since_epoch.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Writes one match to a gzipped JSON lines file in the replay directory.
+pub struct ReplayRecorder {
+    path: PathBuf,
+    writer: GzEncoder<BufWriter<File>>,
+}
+
+impl std::fmt::Debug for ReplayRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReplayRecorder").field("path", &self.path).finish()
+    }
+}
+
+impl ReplayRecorder {
+    /// Starts a replay named after the room and the time the match started.
+    pub fn create(dir: &Path, room: &str, header: &ReplayHeader) -> Result<ReplayRecorder, Error> {
+        fs::create_dir_all(dir).map_err(|e| {
+            format_err!("Failed to create replay directory {}: {}", dir.display(), e)
+        })?;
+
+        let millis = (header.started_at * 1000.0) as u64;
+        let path = dir.join(format!("{}-{}{}", room, millis, REPLAY_EXTENSION));
+        let file = File::create(&path)
+            .map_err(|e| format_err!("Failed to create replay {}: {}", path.display(), e))?;
+
+        let mut recorder = ReplayRecorder {
+            path,
+            writer: GzEncoder::new(BufWriter::new(file), Compression::fast()),
+        };
+        recorder.write_line(header)?;
+
+        info!("Recording replay to {}", recorder.path.display());
+
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, record: &ReplayRecord) {
+        if let Err(e) = self.write_line(record) {
+            error!("Failed to write replay record to {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Writes the gzip trailer. A replay which is dropped without finishing
+    /// is truncated and can't be read back.
+    pub fn finish(self) {
+        let path = self.path;
+        match self.writer.finish().and_then(|mut writer| writer.flush()) {
+            Ok(()) => info!("Finished replay {}", path.display()),
+            Err(e) => error!("Failed to finish replay {}: {}", path.display(), e),
+        }
+    }
+
+    fn write_line<T: serde::Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, value)?;
+        self.writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+/// Team names and colors as of a tick.
+#[derive(Debug)]
+pub struct ReplayTeams {
+    pub tick: u64,
+    pub team_names: HashMap<u32, String>,
+    pub team_colors: HashMap<u32, String>,
+}
+
+/// A replay read back into memory for playback.
+#[derive(Debug)]
+pub struct Replay {
+    pub states: Vec<GameState>,
+    // In tick order, starting with the names from the header
+    teams: Vec<ReplayTeams>,
+}
+
+impl Replay {
+    /// Reads the replay at `path`. A replay which is still being recorded,
+    /// or was cut short by a crash, is read up to the last complete record.
+    pub fn load(path: &Path) -> Result<Replay, Error> {
+        let file = File::open(path)
+            .map_err(|e| format_err!("Failed to open replay {}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+
+        let header_line = lines.next().ok_or_else(|| format_err!("Replay is empty"))??;
+        let header: ReplayHeader = serde_json::from_str(&header_line)?;
+        if header.version > REPLAY_FORMAT_VERSION {
+            bail!("Replay format version {} is not supported", header.version);
+        }
+
+        let mut states = vec![];
+        let mut teams = vec![ReplayTeams {
+            tick: 0,
+            team_names: header.team_names,
+            team_colors: header.team_colors,
+        }];
+
+        for line in lines {
+            let record = match line
+                .map_err(Error::from)
+                .and_then(|line| serde_json::from_str::<ReplayRecord>(&line).map_err(Error::from))
+            {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Replay {} is truncated: {}", path.display(), e);
+                    break;
+                },
+            };
+
+            match record {
+                ReplayRecord::State(state) => states.push(state),
+                ReplayRecord::TeamNames { tick, team_names, team_colors } => {
+                    teams.push(ReplayTeams { tick, team_names, team_colors });
+                },
+                ReplayRecord::Command { .. } | ReplayRecord::GameConfig { .. } => {},
+            }
+        }
+
+        Ok(Replay { states, teams })
+    }
+
+    /// The team names and colors in effect at `tick`.
+    pub fn teams_at(&self, tick: u64) -> &ReplayTeams {
+        self.teams.iter().rev().find(|teams| teams.tick <= tick).unwrap_or(&self.teams[0])
+    }
+
+    /// The index of the first state at or after `tick`, or the last state.
+    pub fn position_of(&self, tick: u64) -> usize {
+        self.states
+            .iter()
+            .position(|state| state.tick >= tick)
+            .unwrap_or_else(|| self.states.len().saturating_sub(1))
+    }
+}
+
+/// Lists the replays in `dir`, newest first.
+pub fn list_replays(dir: &Path) -> Result<Vec<ReplayFile>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut replays = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let id = entry.file_name().to_string_lossy().into_owned();
+        if !id.ends_with(REPLAY_EXTENSION) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        replays.push(ReplayFile {
+            id,
+            size: metadata.len(),
+            modified: metadata.modified().map(unix_time).unwrap_or(0.0),
+        });
+    }
+
+    replays
+        .sort_by(|a, b| b.modified.partial_cmp(&a.modified).unwrap_or(std::cmp::Ordering::Equal));
+
+    Ok(replays)
+}
+
+/// Deletes the oldest replays so at most `keep` remain.
+pub fn prune_replays(dir: &Path, keep: usize) -> Result<(), Error> {
+    for replay in list_replays(dir)?.iter().skip(keep) {
+        info!("Deleting old replay {}", replay.id);
+        fs::remove_file(dir.join(&replay.id))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the replay called `id`, making sure the ID can't be
+/// used to reach outside the replay directory.
+pub fn replay_path(dir: &Path, id: &str) -> Option<PathBuf> {
+    let valid = id.ends_with(REPLAY_EXTENSION)
+        && !id.starts_with('.')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Some(dir.join(id))
+    } else {
+        None
+    }
+}
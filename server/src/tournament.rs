@@ -0,0 +1,571 @@
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TournamentFormat {
+    /// Every entrant plays everyone else in their group once.
+    RoundRobin {
+        group_size: usize,
+    },
+    SingleElimination,
+    DoubleElimination,
+}
+
+impl TournamentFormat {
+    // How many matches an entrant can lose before they're out
+    fn max_losses(self) -> Option<u32> {
+        match self {
+            TournamentFormat::RoundRobin { .. } => None,
+            TournamentFormat::SingleElimination => Some(1),
+            TournamentFormat::DoubleElimination => Some(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentPhase {
+    Playing,
+    Intermission,
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entrant {
+    pub api_key: String,
+    /// The registered display name, which is all the public gets to see.
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+struct Match {
+    round: u32,
+    group: Option<usize>,
+    // Indices into `Tournament::entrants`
+    entrants: [usize; 2],
+    scores: Option<[u32; 2]>,
+    // None for an unplayed match or a round robin draw
+    winner: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchInfo {
+    pub id: usize,
+    pub round: u32,
+    pub group: Option<usize>,
+    pub names: Vec<String>,
+    pub scores: Option<Vec<u32>>,
+    pub winner: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Standing {
+    pub name: String,
+    pub group: Option<usize>,
+    pub played: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// Three per win and one per draw, only used by round robins.
+    pub points: u32,
+    /// Total kills over all matches.
+    pub score: u32,
+    pub eliminated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TournamentStatus {
+    pub format: TournamentFormat,
+    pub phase: TournamentPhase,
+    pub current_match: Option<usize>,
+    pub matches: Vec<MatchInfo>,
+    pub standings: Vec<Standing>,
+    pub champion: Option<String>,
+}
+
+/// The schedule and results of a tournament. Matches are one against one and
+/// played one at a time, in order. Round robins are scheduled up front,
+/// elimination brackets one round at a time as results come in.
+#[derive(Debug)]
+pub struct Tournament {
+    format: TournamentFormat,
+    entrants: Vec<Entrant>,
+    matches: Vec<Match>,
+    // The match being played, or the next one to play
+    current: usize,
+    pub phase: TournamentPhase,
+}
+
+impl Tournament {
+    /// Entrants are seeded in the order given, and the better seed wins ties
+    /// in elimination matches.
+    pub fn new(format: TournamentFormat, entrants: Vec<Entrant>) -> Result<Tournament, String> {
+        if entrants.len() < 2 {
+            return Err("A tournament needs at least two entrants".to_string());
+        }
+        let mut api_keys = HashSet::new();
+        if let Some(entrant) = entrants.iter().find(|entrant| !api_keys.insert(&entrant.api_key)) {
+            return Err(format!("{} is entered more than once", entrant.api_key));
+        }
+
+        let mut tournament = Tournament {
+            format,
+            entrants,
+            matches: vec![],
+            current: 0,
+            phase: TournamentPhase::Playing,
+        };
+
+        match format {
+            TournamentFormat::RoundRobin { group_size } => {
+                if group_size < 2 {
+                    return Err("Groups need at least two entrants".to_string());
+                }
+                tournament.schedule_round_robin(group_size);
+            },
+            TournamentFormat::SingleElimination | TournamentFormat::DoubleElimination => {
+                tournament.schedule_elimination_round();
+            },
+        }
+
+        Ok(tournament)
+    }
+
+    /// The API keys allowed to play the current match, or None once the
+    /// tournament is over.
+    pub fn current_match_keys(&self) -> Option<Vec<String>> {
+        self.matches.get(self.current).map(|m| {
+            m.entrants.iter().map(|&entrant| self.entrants[entrant].api_key.clone()).collect()
+        })
+    }
+
+    /// Records the final scores of the current match, by API key, and moves
+    /// on to the next one.
+    pub fn record_result(&mut self, scores: &HashMap<String, u32>) {
+        let max_losses = self.format.max_losses();
+        let m = match self.matches.get_mut(self.current) {
+            Some(m) => m,
+            None => return,
+        };
+
+        let entrants = &self.entrants;
+        let result = [
+            scores.get(&entrants[m.entrants[0]].api_key).cloned().unwrap_or(0),
+            scores.get(&entrants[m.entrants[1]].api_key).cloned().unwrap_or(0),
+        ];
+        m.scores = Some(result);
+        m.winner = if result[0] > result[1] {
+            Some(m.entrants[0])
+        } else if result[1] > result[0] {
+            Some(m.entrants[1])
+        } else if max_losses.is_some() {
+            // Someone has to go through, so the better seed does
+            Some(m.entrants[0].min(m.entrants[1]))
+        } else {
+            None
+        };
+
+        self.current += 1;
+        if self.current == self.matches.len() && max_losses.is_some() {
+            self.schedule_elimination_round();
+        }
+        if self.current == self.matches.len() {
+            self.phase = TournamentPhase::Finished;
+        }
+    }
+
+    pub fn status(&self) -> TournamentStatus {
+        let matches = self
+            .matches
+            .iter()
+            .enumerate()
+            .map(|(id, m)| MatchInfo {
+                id,
+                round: m.round,
+                group: m.group,
+                names: m.entrants.iter().map(|&e| self.entrants[e].name.clone()).collect(),
+                scores: m.scores.map(|scores| scores.to_vec()),
+                winner: m.winner.map(|e| self.entrants[e].name.clone()),
+            })
+            .collect();
+
+        let current_match =
+            if self.phase == TournamentPhase::Finished { None } else { Some(self.current) };
+
+        TournamentStatus {
+            format: self.format,
+            phase: self.phase,
+            current_match,
+            matches,
+            standings: self.ranked().into_iter().map(|(_, standing)| standing).collect(),
+            champion: self.champion().map(|e| self.entrants[e].name.clone()),
+        }
+    }
+
+    /// Standings with the index of their entrant, best first.
+    fn ranked(&self) -> Vec<(usize, Standing)> {
+        let mut standings: Vec<Standing> = self
+            .entrants
+            .iter()
+            .map(|entrant| Standing { name: entrant.name.clone(), ..Default::default() })
+            .collect();
+
+        for m in self.matches.iter() {
+            for &entrant in m.entrants.iter() {
+                standings[entrant].group = m.group;
+            }
+
+            let scores = match m.scores {
+                Some(scores) => scores,
+                None => continue,
+            };
+
+            for (i, &entrant) in m.entrants.iter().enumerate() {
+                let standing = &mut standings[entrant];
+                standing.played += 1;
+                standing.score += scores[i];
+                match m.winner {
+                    Some(winner) if winner == entrant => {
+                        standing.wins += 1;
+                        standing.points += 3;
+                    },
+                    Some(_) => standing.losses += 1,
+                    None => {
+                        standing.draws += 1;
+                        standing.points += 1;
+                    },
+                }
+            }
+        }
+
+        if let Some(max_losses) = self.format.max_losses() {
+            for standing in standings.iter_mut() {
+                standing.eliminated = standing.losses >= max_losses;
+            }
+        }
+
+        let mut ranked: Vec<(usize, Standing)> = standings.into_iter().enumerate().collect();
+        // Seed order breaks any remaining ties, as the sort is stable
+        ranked.sort_by(|(_, a), (_, b)| {
+            a.group
+                .cmp(&b.group)
+                .then(a.eliminated.cmp(&b.eliminated))
+                .then(b.points.cmp(&a.points))
+                .then(a.losses.cmp(&b.losses))
+                .then(b.score.cmp(&a.score))
+        });
+
+        ranked
+    }
+
+    fn champion(&self) -> Option<usize> {
+        if self.phase != TournamentPhase::Finished {
+            return None;
+        }
+
+        match self.format.max_losses() {
+            Some(max_losses) => {
+                let losses = self.losses();
+                (0..self.entrants.len()).find(|&e| losses[e] < max_losses)
+            },
+            // Only a single group has a clear winner
+            None => {
+                let ranked = self.ranked();
+                let single_group = ranked.iter().all(|(_, s)| s.group == ranked[0].1.group);
+                if single_group {
+                    Some(ranked[0].0)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    fn losses(&self) -> Vec<u32> {
+        let mut losses = vec![0; self.entrants.len()];
+        for m in self.matches.iter() {
+            if let Some(winner) = m.winner {
+                for &entrant in m.entrants.iter().filter(|&&e| e != winner) {
+                    losses[entrant] += 1;
+                }
+            }
+        }
+
+        losses
+    }
+
+    fn schedule_round_robin(&mut self, group_size: usize) {
+        let entrants: Vec<usize> = (0..self.entrants.len()).collect();
+
+        for (group, members) in entrants.chunks(group_size).enumerate() {
+            // The circle method: fix the first slot and rotate the rest, with
+            // a bye slot for odd groups.
+            let mut slots: Vec<Option<usize>> = members.iter().cloned().map(Some).collect();
+            if slots.len() % 2 == 1 {
+                slots.push(None);
+            }
+
+            let n = slots.len();
+            for round in 0..n - 1 {
+                for i in 0..n / 2 {
+                    if let (Some(a), Some(b)) = (slots[i], slots[n - 1 - i]) {
+                        self.matches.push(Match {
+                            round: round as u32 + 1,
+                            group: Some(group),
+                            entrants: [a, b],
+                            scores: None,
+                            winner: None,
+                        });
+                    }
+                }
+                slots[1..].rotate_right(1);
+            }
+        }
+
+        // Play the groups side by side rather than one after the other
+        self.matches.sort_by_key(|m| m.round);
+    }
+
+    /// Pairs up the entrants still in the running who have lost the same
+    /// number of matches, so a double elimination runs a winners and a
+    /// losers bracket. A grand final loss for the unbeaten finalist leaves
+    /// both on one loss, which forces a deciding rematch.
+    fn schedule_elimination_round(&mut self) {
+        let max_losses = match self.format.max_losses() {
+            Some(max_losses) => max_losses,
+            None => return,
+        };
+        let round = self.matches.last().map(|m| m.round + 1).unwrap_or(1);
+        let losses = self.losses();
+
+        let alive: Vec<usize> =
+            (0..self.entrants.len()).filter(|&e| losses[e] < max_losses).collect();
+        if alive.len() < 2 {
+            return;
+        }
+
+        let mut brackets: Vec<Vec<usize>> = (0..max_losses)
+            .map(|l| alive.iter().cloned().filter(|&e| losses[e] == l).collect())
+            .filter(|bracket: &Vec<usize>| !bracket.is_empty())
+            .collect();
+
+        // Nobody left to play in their own bracket, so it's the final
+        if brackets.iter().all(|bracket| bracket.len() < 2) {
+            brackets = vec![alive];
+        }
+
+        for bracket in brackets {
+            // Best seed against worst seed, the middle seed of an odd
+            // bracket gets a bye
+            let n = bracket.len();
+            for i in 0..n / 2 {
+                self.matches.push(Match {
+                    round,
+                    group: None,
+                    entrants: [bracket[i], bracket[n - 1 - i]],
+                    scores: None,
+                    winner: None,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entrants(count: usize) -> Vec<Entrant> {
+        (0..count)
+            .map(|i| Entrant { api_key: format!("key-{}", i), name: format!("team-{}", i) })
+            .collect()
+    }
+
+    /// The entrants of every scheduled match, by round.
+    fn schedule(tournament: &Tournament) -> Vec<(u32, [usize; 2])> {
+        tournament.matches.iter().map(|m| (m.round, m.entrants)).collect()
+    }
+
+    /// Plays the current match, which `winner` has to be in, and wins it.
+    fn win(tournament: &mut Tournament, winner: usize) {
+        let keys = tournament.current_match_keys().expect("the tournament should still run");
+        let winner_key = format!("key-{}", winner);
+        assert!(keys.contains(&winner_key), "{} doesn't play in {:?}", winner_key, keys);
+
+        let scores = keys
+            .into_iter()
+            .map(|key| {
+                let score = if key == winner_key { 5 } else { 1 };
+                (key, score)
+            })
+            .collect();
+        tournament.record_result(&scores);
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(Tournament::new(TournamentFormat::SingleElimination, entrants(1)).is_err());
+
+        let mut twice = entrants(2);
+        twice[1].api_key = twice[0].api_key.clone();
+        assert!(Tournament::new(TournamentFormat::SingleElimination, twice).is_err());
+
+        let format = TournamentFormat::RoundRobin { group_size: 1 };
+        assert!(Tournament::new(format, entrants(4)).is_err());
+    }
+
+    #[test]
+    fn round_robin_pairs_everyone_once() {
+        let format = TournamentFormat::RoundRobin { group_size: 4 };
+        let tournament = Tournament::new(format, entrants(4)).unwrap();
+
+        // The circle method keeps the first seed in place and rotates the rest
+        assert_eq!(
+            schedule(&tournament),
+            vec![(1, [0, 3]), (1, [1, 2]), (2, [0, 2]), (2, [3, 1]), (3, [0, 1]), (3, [2, 3]),]
+        );
+    }
+
+    #[test]
+    fn round_robin_gives_odd_groups_a_bye() {
+        let format = TournamentFormat::RoundRobin { group_size: 5 };
+        let tournament = Tournament::new(format, entrants(5)).unwrap();
+        let matches = schedule(&tournament);
+
+        assert_eq!(matches.len(), 10);
+        let mut pairs = HashSet::new();
+        for &(_, [a, b]) in matches.iter() {
+            assert!(pairs.insert((a.min(b), a.max(b))), "{} and {} meet twice", a, b);
+        }
+
+        // Two matches a round, so one entrant sits out each of the five
+        // rounds, and everyone sits out exactly once
+        let mut byes = vec![0; 5];
+        for round in 1..=5 {
+            let playing: Vec<usize> = matches
+                .iter()
+                .filter(|(r, _)| *r == round)
+                .flat_map(|(_, entrants)| entrants.iter().cloned())
+                .collect();
+            assert_eq!(playing.len(), 4);
+            for entrant in (0..5).filter(|e| !playing.contains(e)) {
+                byes[entrant] += 1;
+            }
+        }
+        assert_eq!(byes, vec![1; 5]);
+    }
+
+    #[test]
+    fn round_robin_groups_play_side_by_side() {
+        let format = TournamentFormat::RoundRobin { group_size: 3 };
+        let mut tournament = Tournament::new(format, entrants(6)).unwrap();
+        let matches = schedule(&tournament);
+
+        assert_eq!(matches.len(), 6);
+        assert!(matches.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        for &(_, [a, b]) in matches.iter() {
+            assert_eq!(a / 3, b / 3, "{} and {} are in different groups", a, b);
+        }
+
+        for _ in 0..matches.len() {
+            let winner = tournament.matches[tournament.current].entrants[0];
+            win(&mut tournament, winner);
+        }
+
+        // Two group winners, but no single champion
+        let status = tournament.status();
+        assert_eq!(status.phase, TournamentPhase::Finished);
+        assert_eq!(status.champion, None);
+        assert_eq!(status.standings[0].group, Some(0));
+        assert_eq!(status.standings[3].group, Some(1));
+    }
+
+    #[test]
+    fn single_elimination() {
+        let mut tournament =
+            Tournament::new(TournamentFormat::SingleElimination, entrants(4)).unwrap();
+        assert_eq!(schedule(&tournament), vec![(1, [0, 3]), (1, [1, 2])]);
+
+        win(&mut tournament, 0);
+        win(&mut tournament, 2);
+        assert_eq!(schedule(&tournament)[2..], [(2, [0, 2])]);
+
+        win(&mut tournament, 2);
+        let status = tournament.status();
+        assert_eq!(status.phase, TournamentPhase::Finished);
+        assert_eq!(status.champion, Some("team-2".to_string()));
+        assert_eq!(status.standings[0].name, "team-2");
+        assert!(status.standings[1..].iter().all(|standing| standing.eliminated));
+        assert_eq!(tournament.current_match_keys(), None);
+    }
+
+    #[test]
+    fn single_elimination_gives_a_bye_to_the_middle_seed() {
+        let mut tournament =
+            Tournament::new(TournamentFormat::SingleElimination, entrants(3)).unwrap();
+        assert_eq!(schedule(&tournament), vec![(1, [0, 2])]);
+
+        win(&mut tournament, 0);
+        assert_eq!(schedule(&tournament)[1..], [(2, [0, 1])]);
+
+        win(&mut tournament, 1);
+        assert_eq!(tournament.status().champion, Some("team-1".to_string()));
+    }
+
+    #[test]
+    fn elimination_draws_go_to_the_better_seed() {
+        let mut tournament =
+            Tournament::new(TournamentFormat::SingleElimination, entrants(2)).unwrap();
+        let scores = vec![("key-0".to_string(), 3), ("key-1".to_string(), 3)].into_iter().collect();
+        tournament.record_result(&scores);
+
+        assert_eq!(tournament.status().champion, Some("team-0".to_string()));
+    }
+
+    #[test]
+    fn double_elimination_runs_a_losers_bracket() {
+        let mut tournament =
+            Tournament::new(TournamentFormat::DoubleElimination, entrants(4)).unwrap();
+        win(&mut tournament, 0);
+        win(&mut tournament, 1);
+
+        // Winners and losers brackets
+        assert_eq!(schedule(&tournament)[2..], [(2, [0, 1]), (2, [2, 3])]);
+        win(&mut tournament, 0);
+        win(&mut tournament, 2);
+
+        // The unbeaten entrant waits for the losers bracket
+        assert_eq!(schedule(&tournament)[4..], [(3, [1, 2])]);
+        win(&mut tournament, 2);
+
+        assert_eq!(schedule(&tournament)[5..], [(4, [0, 2])]);
+        win(&mut tournament, 0);
+
+        let status = tournament.status();
+        assert_eq!(status.phase, TournamentPhase::Finished);
+        assert_eq!(status.champion, Some("team-0".to_string()));
+        assert_eq!(status.standings[0].losses, 0);
+    }
+
+    #[test]
+    fn double_elimination_final_loss_forces_a_rematch() {
+        let mut tournament =
+            Tournament::new(TournamentFormat::DoubleElimination, entrants(4)).unwrap();
+        for &winner in [0, 1, 0, 2, 2].iter() {
+            win(&mut tournament, winner);
+        }
+        assert_eq!(schedule(&tournament)[5..], [(4, [0, 2])]);
+
+        // The finalist from the losers bracket beats the unbeaten one, who
+        // gets a second chance
+        win(&mut tournament, 2);
+        assert_eq!(tournament.phase, TournamentPhase::Playing);
+        assert_eq!(schedule(&tournament)[6..], [(5, [0, 2])]);
+
+        win(&mut tournament, 2);
+        let status = tournament.status();
+        assert_eq!(status.phase, TournamentPhase::Finished);
+        assert_eq!(status.champion, Some("team-2".to_string()));
+        assert!(status.standings[1..].iter().all(|standing| standing.eliminated));
+    }
+}
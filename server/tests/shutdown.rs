@@ -0,0 +1,46 @@
+use std::{sync::mpsc, thread, time::Duration};
+use tokyo::models::ServerToClient;
+use tokyo_server::{AppConfig, GameConfig, Server};
+use tungstenite::{protocol::frame::coding::CloseCode, Error, Message};
+use url::Url;
+
+// Well below the SignalActor's forced exit, so a hang shows up as a failure
+const MAX_SHUTDOWN: Duration = Duration::from_secs(8);
+
+#[test]
+fn shutdown_with_a_connected_bot() {
+    let mut config = AppConfig::new(GameConfig { bound_x: 3500.0, bound_y: 2500.0 });
+    config.dev_mode = true;
+
+    let server = Server::builder(config).bind("127.0.0.1:0").start().unwrap();
+    let url = Url::parse(&format!("ws://{}/socket?key=test&name=test", server.addr())).unwrap();
+    let (mut socket, _) = tungstenite::connect(url).unwrap();
+
+    // Wait until the game has taken the bot in
+    loop {
+        if let Message::Text(text) = socket.read_message().unwrap() {
+            if let Ok(ServerToClient::Id(_)) = serde_json::from_str(&text) {
+                break;
+            }
+        }
+    }
+
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        server.shutdown();
+        let _ = done_tx.send(());
+    });
+    done_rx.recv_timeout(MAX_SHUTDOWN).expect("the server didn't shut down in time");
+
+    // States may still be on the way, but the socket ends with a close frame
+    loop {
+        match socket.read_message() {
+            Ok(_) => {},
+            Err(Error::ConnectionClosed(frame)) => {
+                assert_eq!(frame.map(|frame| frame.code), Some(CloseCode::Away));
+                break;
+            },
+            Err(e) => panic!("the socket ended without a close frame: {}", e),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 const BASE_SHIP_SIZE = 10
+const TICKS_PER_SECOND = 30
 var websocket_status = document.getElementById('websocket-status')
 var chart = document.getElementById('scoreboard')
 var feed = document.getElementById('killfeed')
@@ -20,9 +21,18 @@ window.onload = window.onresize = function () {
   }
 }
 var team_names = {}
-let prev_scoreboard = {}
-let prev_dead = []
-let died = []
+var team_colors = {}
+// RTTs and phases by player ID, from the spectator-only 'info' message
+var player_info = {}
+// The running tournament, if any
+var tournament = null
+var ratings = []
+const params = new URLSearchParams(window.location.search)
+let following = params.has('follow') ? Number(params.get('follow')) : null
+// Set to watch a recorded match from /replays instead of the live game
+const replay_id = params.get('replay')
+let replay_status = null
+let socket = null
 
 var ctx = c.getContext('2d')
 
@@ -31,18 +41,35 @@ function connect (handler) {
   websocket_status.style.borderColor = 'gray'
   const isLocalServer = window.location.host.indexOf('localhost') !== -1
   // const protocol = isLocalServer ? 'ws://' : 'wss://';
-  const socket = new WebSocket(`wss://combat.sege.dev/spectate`)
+  const room = encodeURIComponent(params.get('room') || 'main')
+  const path = replay_id
+    ? `replay/${encodeURIComponent(replay_id)}`
+    : `spectate?room=${room}`
+  socket = new WebSocket(`wss://combat.sege.dev/${path}`)
   socket.addEventListener('open', function (event) {
     websocket_status.innerText = 'connected'
     websocket_status.style.borderColor = 'white'
+    if (replay_id) {
+      document.getElementById('replay-controls').hidden = false
+      return
+    }
+    if (params.has('rate')) {
+      send_command('rate', Number(params.get('rate')))
+    }
+    if (following !== null) {
+      send_command('follow', following)
+    }
   })
 
   socket.addEventListener('close', function (event) {
     websocket_status.innerText = 'disconnected'
     websocket_status.style.borderColor = 'orange'
-    setTimeout(function () {
-      connect(handler)
-    }, 1000)
+    // Reconnecting would restart a replay from the beginning
+    if (!replay_id) {
+      setTimeout(function () {
+        connect(handler)
+      }, 1000)
+    }
   })
 
   socket.addEventListener('error', function (event) {
@@ -57,6 +84,56 @@ function connect (handler) {
   })
 }
 
+function send_command (e, data) {
+  if (socket && socket.readyState === WebSocket.OPEN) {
+    socket.send(JSON.stringify({ e, data }))
+  }
+}
+
+function toggle_follow (player_id) {
+  send_command('follow', following === player_id ? null : player_id)
+}
+
+const replay_seek = document.getElementById('replay-seek')
+let seeking = false
+replay_seek.addEventListener('input', function () {
+  seeking = true
+})
+replay_seek.addEventListener('change', function () {
+  seeking = false
+  send_command('seek', Number(replay_seek.value))
+})
+document.getElementById('replay-rewind').addEventListener('click', function () {
+  if (replay_status) {
+    send_command(
+      'seek',
+      Math.max(replay_status.first_tick, replay_status.tick - 10 * TICKS_PER_SECOND)
+    )
+  }
+})
+document.getElementById('replay-play').addEventListener('click', function () {
+  if (replay_status) {
+    send_command(replay_status.playing ? 'pause' : 'play')
+  }
+})
+document.getElementById('replay-speed').addEventListener('change', function (event) {
+  send_command('speed', Number(event.target.value))
+})
+
+function update_replay_controls (status) {
+  replay_status = status
+  replay_seek.min = status.first_tick
+  replay_seek.max = status.last_tick
+  if (!seeking) {
+    replay_seek.value = status.tick
+  }
+  document.getElementById('replay-play').innerText = status.playing
+    ? 'pause'
+    : 'play'
+  document.getElementById('replay-speed').value = String(status.speed)
+  document.getElementById('replay-tick').innerText = `tick ${status.tick} / ${status.last_tick}`
+}
+
 class Item {
   constructor (obj) {
     this.x = obj.x
@@ -124,11 +201,17 @@ class Ship {
     ctx.lineTo(0, shipSize)
     ctx.lineTo(shipSize * 0.8, -shipSize)
     ctx.lineTo(-shipSize * 0.8, -shipSize)
-    ctx.fillStyle = '#ff0000'
+    ctx.fillStyle = team_colors[this.id] || '#ff0000'
     ctx.fill()
     ctx.stroke()
     ctx.fillStyle = oldFill
 
+    if (this.id === following) {
+      ctx.beginPath()
+      ctx.arc(0, 0, shipSize * 2, 0, 2 * Math.PI)
+      ctx.stroke()
+    }
+
     // draw team name
     ctx.rotate(-this.angle + Math.PI / 2.0) // please don't ask me about this math
     oldFill = ctx.fillStyle
@@ -186,11 +269,45 @@ let initCanvas = false
 connect(function (json) {
   if (json.e === 'teamnames') {
     team_names = json.data
+  } else if (json.e === 'teamcolors') {
+    team_colors = json.data
+  } else if (json.e === 'shutdown') {
+    update_killfeed(`Server: ${json.data}`)
+  } else if (json.e === 'closed') {
+    // The server moves us back to the main room on its own
+    update_killfeed(`Match ${json.data} is over`)
+  } else if (json.e === 'ratings') {
+    ratings = json.data
+    draw_scoreboard(last_drawn_scoreboard)
+  } else if (json.e === 'tournament') {
+    tournament = json.data
+    draw_scoreboard(last_drawn_scoreboard)
+  } else if (json.e === 'replay') {
+    update_replay_controls(json.data)
+  } else if (json.e === 'error') {
+    console.error('Spectator error:', json.data)
+  } else if (json.e === 'following') {
+    following = json.data
+    draw_scoreboard(last_drawn_scoreboard)
+  } else if (json.e === 'info') {
+    player_info = {}
+    for (const player of json.data.players) {
+      player_info[player.id] = player
+    }
+    draw_scoreboard(last_drawn_scoreboard)
+  } else if (json.e === 'events') {
+    for (const event of json.data) {
+      if (event.type === 'kill') {
+        update_killfeed(
+          `${team_names[event.killer]} killed ${team_names[event.victim]}`
+        )
+      }
+    }
   } else if (json.e === 'state') {
     const data = json.data
 
-    if (JSON.stringify(prev_scoreboard) === '{}') {
-      prev_scoreboard = data.scoreboard
+    if (replay_status) {
+      update_replay_controls(Object.assign(replay_status, { tick: data.tick }))
     }
 
     ctx.save()
@@ -227,26 +344,12 @@ connect(function (json) {
 
     ctx.restore()
 
-    if (JSON.stringify(prev_dead) !== JSON.stringify(data.dead)) {
-      for (const prev_dead_player of prev_dead) {
-        const index = died_on_feed.findIndex(
-          player_id => player_id === prev_dead_player.player.id
-        )
-        if (index != -1) {
-          died_on_feed.splice(index, 1)
-          update_killfeed(
-            `${team_names[dead.player.id]} (${dead.player.id}) respawned`
-          )
-        }
-      }
-      prev_dead = data.dead
-    }
+    draw_game_speed(data)
 
     if (
       JSON.stringify(data.scoreboard) !== JSON.stringify(last_drawn_scoreboard)
     ) {
       draw_scoreboard(data.scoreboard)
-      draw_killfeed(data.dead, data.scoreboard)
       last_drawn_scoreboard = data.scoreboard
     }
   }
@@ -285,6 +388,8 @@ function draw_scoreboard (scoreboard) {
           More Bullet
         </td>
       </tr>`
+  tableHtml += tournament_html()
+  tableHtml += ratings_html()
   tableHtml += `<tr style="padding-top: 20px;">
         <td colspan="3" class="heading"><b>Leaderboard</b></td>
       </tr>`
@@ -292,9 +397,15 @@ function draw_scoreboard (scoreboard) {
     const player_id = sorted_players[i]
     const player_score = String(scoreboard[player_id]).padEnd(3)
     const team_name = sanitizeHTML(team_names[player_id])
+    const info = player_info[player_id] || {}
+    const rtt = info.rtt_ms ? `${Math.round(info.rtt_ms)}ms` : 'unknown'
+    const classes = [`rank-${i + 1}`, info.phase || 'alive']
+    if (Number(player_id) === following) {
+      classes.push('following')
+    }
 
     tableHtml += `
-            <tr class="rank-${i + 1}">
+            <tr class="${classes.join(' ')}" title="RTT: ${rtt}" onclick="toggle_follow(${Number(player_id)})">
               <td class="rank">${i + 1}</td>
               <td class="name">${team_name}</td>
               <td class="score">${player_score}</td>
@@ -303,47 +414,86 @@ function draw_scoreboard (scoreboard) {
   chart.innerHTML = tableHtml + '</tbody>'
 }
 
+function tournament_html () {
+  if (!tournament) {
+    return ''
+  }
+
+  let html = `<tr style="padding-top: 20px;">
+        <td colspan="3" class="heading"><b>Tournament</b></td>
+      </tr>`
+
+  const current = tournament.matches[tournament.current_match]
+  if (tournament.champion) {
+    html += `<tr><td colspan="3">Champion: ${sanitizeHTML(tournament.champion)}</td></tr>`
+  } else if (current) {
+    const names = current.names.map(sanitizeHTML).join(' vs ')
+    const label = tournament.phase === 'intermission' ? 'Next' : 'Now'
+    html += `<tr><td colspan="3">${label}: ${names}</td></tr>`
+  }
+
+  const round_robin = tournament.format.type === 'round_robin'
+  tournament.standings.forEach(function (standing, i) {
+    const record = round_robin
+      ? `${standing.points}pt`
+      : `${standing.wins}-${standing.losses}`
+    html += `
+            <tr class="${standing.eliminated ? 'dead' : ''}">
+              <td class="rank">${i + 1}</td>
+              <td class="name">${sanitizeHTML(standing.name)}</td>
+              <td class="score">${record}</td>
+            </tr>`
+  })
+
+  return html
+}
+
+function ratings_html () {
+  if (!ratings.length) {
+    return ''
+  }
+
+  let html = `<tr style="padding-top: 20px;">
+        <td colspan="3" class="heading"><b>Ratings</b></td>
+      </tr>`
+  ratings.slice(0, 10).forEach(function (entry, i) {
+    html += `
+            <tr title="${entry.wins} wins in ${entry.matches} matches">
+              <td class="rank">${i + 1}</td>
+              <td class="name">${sanitizeHTML(entry.team_name)}</td>
+              <td class="score">${entry.rating}</td>
+            </tr>`
+  })
+
+  return html
+}
+
+function draw_game_speed (data) {
+  let text = null
+  if (data.paused) {
+    text = 'PAUSED'
+  } else if (data.time_scale && data.time_scale !== 1) {
+    text = `${data.time_scale}x SPEED`
+  }
+  if (!text) {
+    return
+  }
+
+  ctx.save()
+  ctx.font = '24px monospace'
+  ctx.textAlign = 'right'
+  ctx.textBaseline = 'top'
+  ctx.fillStyle = '#ffa502'
+  ctx.fillText(text, c.width - 10, 10)
+  ctx.restore()
+}
+
 function update_killfeed (text) {
   const item = document.createElement('div')
   item.innerText = text
   feed.insertBefore(item, feed_anchor)
 }
 
-const died_on_feed = []
-function draw_killfeed (dead, scoreboard) {
-  if (dead.length) {
-    for (const { player: dead_player } of dead) {
-      if (died_on_feed.includes(dead_player.id)) {
-        continue
-      } else {
-        dead_player.push(dead_player.id)
-        update_killfeed(`${team_names[dead_player.id]} died`)
-        // console.log('new dead player', dead_player.id, team_names[dead_player.id])
-        // already_dead.push(dead_player.id)
-        // for (const [killer_id, score] of Object.entries(scoreboard)) {
-        //   const prev_score = prev_scoreboard[killer_id]
-        //   if (killer_id === dead_player.id.toString()) {
-        //     continue
-        //   }
-        //   if (score > prev_score) {
-        //     console.log(team_names[killer_id], score, prev_score)
-        //     prev_scoreboard = scoreboard
-        //     const item = document.createElement('div')
-        //     console.log(`${team_names[killer_id]} (${killer_id}) killed ${team_names[dead_player.id]} (${dead_player.id})`)
-        //     item.innerText = `${team_names[killer_id]} killed ${team_names[dead_player.id]}`
-        //     feed.prepend(item)
-        //     break
-        //   }
-        // }
-      }
-    }
-  } else {
-    console.log('reset')
-    died_on_feed = []
-  }
-  prev_scoreboard = scoreboard
-}
-
 const observer = new MutationObserver(function (mutationsList, observer) {
   for (let mutation of mutationsList) {
     if (mutation.type === 'childList') {